@@ -0,0 +1,326 @@
+use nalgebra::{Isometry3, Point3, Quaternion, Translation3, UnitQuaternion, Vector2, Vector3};
+use std::f32::consts::FRAC_PI_2;
+
+const SENSITIVITY: f32 = 0.002;
+// Keeps pitch just short of straight up or down, where yaw becomes ambiguous
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+// What the user did since the last frame
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CameraInput {
+  // Mouse movement in pixels
+  pub look: Vector2<f32>,
+  // Wheel steps, positive zooms out
+  pub zoom: f32,
+  // Right, up and forward in -1..=1
+  pub movement: Vector3<f32>,
+}
+
+pub trait CameraController {
+  // Returns the view, the transform from world space to camera space, for this frame
+  fn update(&mut self, target: &Isometry3<f32>, input: &CameraInput, dt: f32) -> Isometry3<f32>;
+  // Whether movement keys steer the camera rather than the player
+  fn captures_movement(&self) -> bool {
+    false
+  }
+}
+
+// Frame rate independent exponential approach of `current` to `goal`
+fn smooth(current: f32, goal: f32, rate: f32, dt: f32) -> f32 {
+  if rate.is_infinite() {
+    return goal;
+  }
+  current + (goal - current) * (1. - (-rate * dt).exp())
+}
+
+// Critically damped spring towards `goal`, `velocity` carries over between frames
+fn smooth_damp(
+  current: Vector3<f32>,
+  goal: Vector3<f32>,
+  velocity: &mut Vector3<f32>,
+  smooth_time: f32,
+  dt: f32,
+) -> Vector3<f32> {
+  if smooth_time <= 0. {
+    *velocity = Vector3::zeros();
+    return goal;
+  }
+  let omega = 2. / smooth_time;
+  let x = omega * dt;
+  // Pade approximation of exp(-x), stable for large steps
+  let decay = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+  let change = current - goal;
+  let temp = (*velocity + change * omega) * dt;
+  *velocity = (*velocity - temp * omega) * decay;
+  goal + (change + temp) * decay
+}
+
+fn look_rotation(yaw: f32, pitch: f32) -> UnitQuaternion<f32> {
+  UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw)
+    * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), pitch)
+}
+
+fn apply_look(yaw: &mut f32, pitch: &mut f32, look: &Vector2<f32>) {
+  *yaw -= look.x * SENSITIVITY;
+  *pitch = (*pitch - look.y * SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+}
+
+// Which part of the target's rotation the camera turns with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Inherit {
+  Full,
+  // Only the heading around the world's y axis, so rolling doesn't spin the view
+  Yaw,
+  Position,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Follow {
+  pub inherit: Inherit,
+  // Roughly how many seconds the camera takes to catch up, zero snaps to the target
+  pub smooth_time: f32,
+  // How quickly the inherited rotation catches up, per second; infinite for no smoothing
+  pub rotation_rate: f32,
+  // Seconds of the target's velocity to lead it by
+  pub look_ahead: f32,
+  // Distance kept from anything between the target and the camera
+  pub margin: f32,
+}
+
+impl Default for Follow {
+  fn default() -> Self {
+    Self {
+      inherit: Inherit::Full,
+      smooth_time: 0.1,
+      rotation_rate: 10.,
+      look_ahead: 0.,
+      margin: 0.3,
+    }
+  }
+}
+
+// Turns the raw target into the smoothed one that controllers look at
+pub struct Follower {
+  pub follow: Follow,
+  position: Option<Vector3<f32>>,
+  velocity: Vector3<f32>,
+  rotation: UnitQuaternion<f32>,
+  last: Option<Vector3<f32>>,
+  lead: Vector3<f32>,
+}
+
+impl Follower {
+  pub fn new(follow: Follow) -> Self {
+    Self {
+      follow,
+      position: None,
+      velocity: Vector3::zeros(),
+      rotation: UnitQuaternion::identity(),
+      last: None,
+      lead: Vector3::zeros(),
+    }
+  }
+  pub fn update(&mut self, target: &Isometry3<f32>, dt: f32) -> Isometry3<f32> {
+    let raw = target.translation.vector;
+    if dt > 0. {
+      if let Some(last) = self.last {
+        let velocity = (raw - last) / dt;
+        self.lead += (velocity - self.lead) * (1. - (-10. * dt).exp());
+      }
+      self.last = Some(raw);
+    }
+    let goal = raw + self.lead * self.follow.look_ahead;
+    let first = self.position.is_none();
+    let position = match self.position {
+      Some(position) => smooth_damp(
+        position,
+        goal,
+        &mut self.velocity,
+        self.follow.smooth_time,
+        dt,
+      ),
+      None => goal,
+    };
+    self.position = Some(position);
+    let rotation = match self.follow.inherit {
+      Inherit::Full => target.rotation,
+      Inherit::Yaw => {
+        let q = target.rotation.quaternion();
+        UnitQuaternion::try_new(Quaternion::new(q.w, 0., q.j, 0.), f32::EPSILON)
+          .unwrap_or_else(UnitQuaternion::identity)
+      }
+      Inherit::Position => UnitQuaternion::identity(),
+    };
+    self.rotation = if first || self.follow.rotation_rate.is_infinite() {
+      rotation
+    } else {
+      let blend = 1. - (-self.follow.rotation_rate * dt).exp();
+      // Half turns have no unique path, so those snap
+      self
+        .rotation
+        .try_slerp(&rotation, blend, f32::EPSILON)
+        .unwrap_or(rotation)
+    };
+    Isometry3::from_parts(position.into(), self.rotation)
+  }
+}
+
+// Circles the target at a distance, in the target's frame
+pub struct Orbit {
+  pub yaw: f32,
+  pub pitch: f32,
+  pub distance: f32,
+  pub min_distance: f32,
+  pub max_distance: f32,
+  // How quickly zooming catches up, per second
+  pub zoom_rate: f32,
+  goal_distance: f32,
+}
+
+impl Orbit {
+  pub fn new(distance: f32) -> Self {
+    Self {
+      yaw: 0.,
+      pitch: 0.,
+      distance,
+      min_distance: 2.,
+      max_distance: 1000.,
+      zoom_rate: 15.,
+      goal_distance: distance,
+    }
+  }
+}
+
+impl CameraController for Orbit {
+  fn update(&mut self, target: &Isometry3<f32>, input: &CameraInput, dt: f32) -> Isometry3<f32> {
+    apply_look(&mut self.yaw, &mut self.pitch, &input.look);
+    self.goal_distance =
+      (self.goal_distance * 1.05f32.powf(input.zoom)).clamp(self.min_distance, self.max_distance);
+    self.distance = smooth(self.distance, self.goal_distance, self.zoom_rate, dt);
+    let eye = look_rotation(self.yaw, self.pitch) * Translation3::new(0., 0., self.distance);
+    (target * eye).inverse()
+  }
+}
+
+// Looks out from the target, e.g. the player's eyes
+pub struct FirstPerson {
+  pub yaw: f32,
+  pub pitch: f32,
+  pub eye_height: f32,
+  // How quickly the view follows the mouse, per second; infinite for no smoothing
+  pub look_rate: f32,
+  view_yaw: f32,
+  view_pitch: f32,
+}
+
+impl FirstPerson {
+  pub fn new(eye_height: f32) -> Self {
+    Self {
+      yaw: 0.,
+      pitch: 0.,
+      eye_height,
+      look_rate: 30.,
+      view_yaw: 0.,
+      view_pitch: 0.,
+    }
+  }
+}
+
+impl CameraController for FirstPerson {
+  fn update(&mut self, target: &Isometry3<f32>, input: &CameraInput, dt: f32) -> Isometry3<f32> {
+    apply_look(&mut self.yaw, &mut self.pitch, &input.look);
+    self.view_yaw = smooth(self.view_yaw, self.yaw, self.look_rate, dt);
+    self.view_pitch = smooth(self.view_pitch, self.pitch, self.look_rate, dt);
+    let eye = Isometry3::from_parts(
+      Translation3::new(0., self.eye_height, 0.),
+      look_rotation(self.view_yaw, self.view_pitch),
+    );
+    (target * eye).inverse()
+  }
+}
+
+// Noclip camera that ignores the target and flies with the movement keys
+pub struct FreeFly {
+  pub position: Point3<f32>,
+  pub yaw: f32,
+  pub pitch: f32,
+  pub speed: f32,
+  // How quickly the camera reaches full speed and stops, per second
+  pub acceleration: f32,
+  velocity: Vector3<f32>,
+}
+
+impl FreeFly {
+  pub fn new(position: Point3<f32>) -> Self {
+    Self {
+      position,
+      yaw: 0.,
+      pitch: 0.,
+      speed: 20.,
+      acceleration: 8.,
+      velocity: Vector3::zeros(),
+    }
+  }
+}
+
+impl CameraController for FreeFly {
+  fn update(&mut self, _target: &Isometry3<f32>, input: &CameraInput, dt: f32) -> Isometry3<f32> {
+    apply_look(&mut self.yaw, &mut self.pitch, &input.look);
+    let rotation = look_rotation(self.yaw, self.pitch);
+    // Forward is -z in camera space
+    let movement = Vector3::new(input.movement.x, input.movement.y, -input.movement.z);
+    let goal = rotation * movement * self.speed;
+    let blend = 1. - (-self.acceleration * dt).exp();
+    self.velocity += (goal - self.velocity) * blend;
+    self.position += self.velocity * dt;
+    Isometry3::from_parts(self.position.into(), rotation).inverse()
+  }
+  fn captures_movement(&self) -> bool {
+    true
+  }
+}
+
+// Trails behind the target and lags on a spring so fast turns stay readable
+pub struct Chase {
+  pub distance: f32,
+  pub height: f32,
+  // Spring rate per second, higher is stiffer
+  pub stiffness: f32,
+  // Mouse adjustable angle around the target, relative to behind it
+  pub yaw: f32,
+  pub pitch: f32,
+  position: Option<Point3<f32>>,
+}
+
+impl Chase {
+  pub fn new(distance: f32, height: f32) -> Self {
+    Self {
+      distance,
+      height,
+      stiffness: 5.,
+      yaw: 0.,
+      pitch: -0.2,
+      position: None,
+    }
+  }
+}
+
+impl CameraController for Chase {
+  fn update(&mut self, target: &Isometry3<f32>, input: &CameraInput, dt: f32) -> Isometry3<f32> {
+    apply_look(&mut self.yaw, &mut self.pitch, &input.look);
+    self.distance = (self.distance * 1.05f32.powf(input.zoom)).max(1.);
+    let offset = look_rotation(self.yaw, self.pitch) * Vector3::new(0., 0., self.distance);
+    let goal = target * Point3::from(offset + Vector3::new(0., self.height, 0.));
+    let position = match self.position {
+      Some(position) => {
+        let blend = 1. - (-self.stiffness * dt).exp();
+        position + (goal - position) * blend
+      }
+      None => goal,
+    };
+    self.position = Some(position);
+    let focus = target.translation.vector.into();
+    let up = target.rotation * Vector3::y();
+    Isometry3::look_at_rh(&position, &focus, &up)
+  }
+}
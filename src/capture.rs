@@ -0,0 +1,280 @@
+use crate::iter_to_array;
+use gloo_utils::document;
+use js_sys::{Function, Promise, Uint8Array};
+use std::future::Future;
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, ImageData, Url};
+
+pub struct Image {
+  pub width: u32,
+  pub height: u32,
+  pub data: Vec<u8>,
+}
+
+impl From<ImageData> for Image {
+  fn from(image: ImageData) -> Self {
+    Self {
+      width: image.width(),
+      height: image.height(),
+      data: image.data().0,
+    }
+  }
+}
+
+impl Image {
+  // Tightly packs a texture readback whose rows are padded to `bytes_per_row`
+  pub fn from_padded(
+    padded: &[u8],
+    width: u32,
+    height: u32,
+    bytes_per_row: u32,
+    bgra: bool,
+  ) -> Self {
+    let row_len = width as usize * 4;
+    let mut data = Vec::with_capacity(row_len * height as usize);
+    for row in padded.chunks(bytes_per_row as usize).take(height as usize) {
+      data.extend_from_slice(&row[..row_len]);
+    }
+    if bgra {
+      for pixel in data.chunks_mut(4) {
+        pixel.swap(0, 2);
+      }
+    }
+    Self {
+      width,
+      height,
+      data,
+    }
+  }
+  pub fn to_image_data(&self) -> Result<ImageData, JsValue> {
+    ImageData::new_with_u8_clamped_array_and_sh(Clamped(&self.data), self.width, self.height)
+  }
+  pub fn to_png(&self) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((self.width as usize * 4 + 1) * self.height as usize);
+    for row in self.data.chunks(self.width as usize * 4) {
+      // filter type: none
+      raw.push(0);
+      raw.extend_from_slice(row);
+    }
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&self.width.to_be_bytes());
+    header.extend_from_slice(&self.height.to_be_bytes());
+    // 8 bit depth, RGBA, deflate, default filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+  }
+  pub fn download(&self, name: &str) -> Result<(), JsValue> {
+    download(&self.to_png(), name, "image/png")
+  }
+}
+
+pub fn download(bytes: &[u8], name: &str, mime: &str) -> Result<(), JsValue> {
+  let bytes = Uint8Array::from(bytes);
+  let blob = Blob::new_with_u8_array_sequence_and_options(
+    &iter_to_array([bytes]),
+    BlobPropertyBag::new().type_(mime),
+  )?;
+  download_blob(&blob, name)
+}
+
+pub fn download_blob(blob: &Blob, name: &str) -> Result<(), JsValue> {
+  let url = Url::create_object_url_with_blob(blob)?;
+  let anchor = document()
+    .create_element("a")?
+    .dyn_into::<HtmlAnchorElement>()?;
+  anchor.set_href(&url);
+  anchor.set_download(name);
+  anchor.click();
+  Url::revoke_object_url(&url)
+}
+
+// Writes an uncompressed zip a file at a time, so many files go out as one download; browsers
+// throttle a page that starts lots of them. Every part is copied out to JS as it's added, so
+// the archive never has to fit in wasm memory. No zip64, so it stays under 4 GiB
+#[derive(Default)]
+pub struct ZipWriter {
+  parts: Vec<Uint8Array>,
+  central: Vec<u8>,
+  len: u64,
+  count: u16,
+}
+
+// 1980-01-01, the earliest date zip can store
+const ZIP_DATE: u16 = 0x21;
+
+fn zip_u32(value: u64) -> Result<u32, JsValue> {
+  u32::try_from(value).map_err(|_| JsValue::from_str("Zip archives can't be larger than 4 GiB"))
+}
+
+impl ZipWriter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn add(&mut self, name: &str, data: &[u8]) -> Result<(), JsValue> {
+    let offset = zip_u32(self.len)?;
+    let size = zip_u32(data.len() as u64)?;
+    self.count = self
+      .count
+      .checked_add(1)
+      .ok_or_else(|| JsValue::from_str("Zip archives can't hold more than 65535 files"))?;
+    let mut fields = Vec::with_capacity(26);
+    // version needed, flags, stored, time, date
+    for value in [20u16, 0, 0, 0, ZIP_DATE] {
+      fields.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [crc32(data), size, size] {
+      fields.extend_from_slice(&value.to_le_bytes());
+    }
+    // name length, no extra field
+    fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    fields.extend_from_slice(&[0, 0]);
+
+    let mut header = 0x04034b50u32.to_le_bytes().to_vec();
+    header.extend_from_slice(&fields);
+    header.extend_from_slice(name.as_bytes());
+    self.len += (header.len() + data.len()) as u64;
+    // Checked before anything is kept, so a failed add leaves the archive as it was
+    zip_u32(self.len)?;
+    self.parts.push(Uint8Array::from(&header[..]));
+    self.parts.push(Uint8Array::from(data));
+
+    self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    // version made by
+    self.central.extend_from_slice(&20u16.to_le_bytes());
+    self.central.extend_from_slice(&fields);
+    // no comment, disk 0, no attributes
+    self.central.extend_from_slice(&[0; 10]);
+    self.central.extend_from_slice(&offset.to_le_bytes());
+    self.central.extend_from_slice(name.as_bytes());
+    Ok(())
+  }
+  pub fn finish(mut self) -> Result<Blob, JsValue> {
+    let start = zip_u32(self.len)?;
+    let size = zip_u32(self.central.len() as u64)?;
+    zip_u32(self.len + self.central.len() as u64 + 22)?;
+    let mut end = std::mem::take(&mut self.central);
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    // this disk, the disk the directory starts on
+    end.extend_from_slice(&[0, 0, 0, 0]);
+    end.extend_from_slice(&self.count.to_le_bytes());
+    end.extend_from_slice(&self.count.to_le_bytes());
+    end.extend_from_slice(&size.to_le_bytes());
+    end.extend_from_slice(&start.to_le_bytes());
+    // no comment
+    end.extend_from_slice(&[0, 0]);
+    self.parts.push(Uint8Array::from(&end[..]));
+    Blob::new_with_u8_array_sequence_and_options(
+      &iter_to_array(self.parts),
+      BlobPropertyBag::new().type_("application/zip"),
+    )
+  }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = png.len();
+  png.extend_from_slice(kind);
+  png.extend_from_slice(data);
+  let crc = crc32(&png[start..]);
+  png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Deflate with uncompressed blocks only; big files but no encoder dependency
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 11);
+  out.extend_from_slice(&[0x78, 0x01]);
+  if data.is_empty() {
+    out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+  }
+  let blocks = data.chunks(65535);
+  let last = blocks.len().saturating_sub(1);
+  for (i, block) in blocks.enumerate() {
+    let len = block.len() as u16;
+    out.push((i == last) as u8);
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&(!len).to_le_bytes());
+    out.extend_from_slice(block);
+  }
+  out.extend_from_slice(&adler32(data).to_be_bytes());
+  out
+}
+
+const fn crc_table() -> [u32; 256] {
+  let mut table = [0u32; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut c = i as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      c = if c & 1 == 1 {
+        0xedb88320 ^ (c >> 1)
+      } else {
+        c >> 1
+      };
+      bit += 1;
+    }
+    table[i] = c;
+    i += 1;
+  }
+  table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc32(data: &[u8]) -> u32 {
+  !data.iter().fold(!0u32, |crc, byte| {
+    CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+  })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for chunk in data.chunks(5552) {
+    for byte in chunk {
+      a += *byte as u32;
+      b += a;
+    }
+    a %= 65521;
+    b %= 65521;
+  }
+  (b << 16) | a
+}
+
+// A pending request for the frame rendered `delay` frames from now
+pub struct Capture {
+  pub delay: u32,
+  resolve: Function,
+  reject: Function,
+}
+
+impl Capture {
+  pub fn new(delay: u32) -> (Self, impl Future<Output = Result<Image, JsValue>>) {
+    let mut handles = None;
+    let promise = Promise::new(&mut |resolve, reject| handles = Some((resolve, reject)));
+    let (resolve, reject) = handles.expect("Promise executor runs synchronously");
+    let future = async move {
+      let image = JsFuture::from(promise).await?.dyn_into::<ImageData>()?;
+      Ok(Image::from(image))
+    };
+    (
+      Self {
+        delay,
+        resolve,
+        reject,
+      },
+      future,
+    )
+  }
+  pub fn resolve(&self, image: &Result<ImageData, JsValue>) {
+    let _ = match image {
+      Ok(image) => self.resolve.call1(&JsValue::NULL, image),
+      Err(err) => self.reject.call1(&JsValue::NULL, err),
+    };
+  }
+}
@@ -0,0 +1,126 @@
+use crate::Scene;
+use nalgebra::{Unit, UnitQuaternion, Vector3};
+use rapier3d::control::{CharacterAutostep, CharacterLength, KinematicCharacterController};
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterConfig {
+  pub speed: f32,
+  pub jump_speed: f32,
+  // Radians
+  pub max_slope_climb_angle: f32,
+  pub min_slope_slide_angle: f32,
+  pub step_height: f32,
+  pub step_min_width: f32,
+  pub snap_to_ground: f32,
+  // Gap kept between the character and the ground, in world units
+  pub offset: f32,
+  // Mass used to push dynamic bodies out of the way, `None` to pass through them
+  pub push_mass: Option<f32>,
+}
+
+impl Default for CharacterConfig {
+  fn default() -> Self {
+    Self {
+      speed: 5.,
+      jump_speed: 6.,
+      max_slope_climb_angle: PI / 4.,
+      min_slope_slide_angle: PI / 3.,
+      step_height: 0.3,
+      step_min_width: 0.2,
+      snap_to_ground: 0.3,
+      offset: 0.02,
+      push_mass: Some(1.),
+    }
+  }
+}
+
+// Drives a kinematic position-based entity over the terrain, "up" is always away from gravity
+pub struct Character {
+  pub config: CharacterConfig,
+  entity: String,
+  fall_speed: f32,
+  grounded: bool,
+  jumping: bool,
+}
+
+impl Character {
+  pub fn new(entity: &str, config: CharacterConfig) -> Self {
+    Self {
+      config,
+      entity: entity.to_owned(),
+      fall_speed: 0.,
+      grounded: false,
+      jumping: false,
+    }
+  }
+  pub fn grounded(&self) -> bool {
+    self.grounded
+  }
+  // Jumps on the next update if the character is on the ground by then
+  pub fn jump(&mut self) {
+    self.jumping = true;
+  }
+  fn controller(&self, up: Unit<Vector3<f32>>) -> KinematicCharacterController {
+    let config = &self.config;
+    KinematicCharacterController {
+      up,
+      offset: CharacterLength::Absolute(config.offset),
+      max_slope_climb_angle: config.max_slope_climb_angle,
+      min_slope_slide_angle: config.min_slope_slide_angle,
+      autostep: Some(CharacterAutostep {
+        max_height: CharacterLength::Absolute(config.step_height),
+        min_width: CharacterLength::Absolute(config.step_min_width),
+        include_dynamic_bodies: false,
+      }),
+      snap_to_ground: Some(CharacterLength::Absolute(config.snap_to_ground)),
+      ..Default::default()
+    }
+  }
+  // `forward` and `right` are the movement input in -1..=1, relative to where the camera looks
+  pub fn update(
+    &mut self,
+    scene: &mut Scene,
+    forward: f32,
+    right: f32,
+    camera: &UnitQuaternion<f32>,
+    dt: f32,
+  ) -> Option<()> {
+    let gravity = scene.gravity_at(&self.entity)?;
+    let up = Unit::try_new(-gravity, f32::EPSILON).unwrap_or(Vector3::y_axis());
+
+    let flatten = |v: Vector3<f32>| {
+      let v = v - up.into_inner() * v.dot(&up);
+      v.try_normalize(f32::EPSILON).unwrap_or_default()
+    };
+    let heading = flatten(camera * -Vector3::z());
+    let side = flatten(camera * Vector3::x());
+    let walk = (heading * forward + side * right)
+      .try_normalize(f32::EPSILON)
+      .unwrap_or_default();
+
+    if self.grounded && self.jumping {
+      self.fall_speed = -self.config.jump_speed;
+    }
+    self.jumping = false;
+    self.fall_speed += gravity.norm() * dt;
+    let desired = (walk * self.config.speed - up.into_inner() * self.fall_speed) * dt;
+
+    // Stand upright on the planet without spinning around the up axis
+    let rotation =
+      UnitQuaternion::rotation_between(&Vector3::y(), &up).unwrap_or(UnitQuaternion::identity());
+    let movement = scene.move_character(
+      &self.entity,
+      &self.controller(up),
+      desired,
+      rotation,
+      self.config.push_mass,
+      dt,
+    )?;
+    self.grounded = movement.grounded;
+    if self.grounded && self.fall_speed > 0. {
+      self.fall_speed = 0.;
+    }
+    Some(())
+  }
+}
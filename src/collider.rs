@@ -0,0 +1,145 @@
+use crate::Geometry;
+use nalgebra::{Isometry3, Point3, Vector3};
+use rapier3d::{
+  geometry::{ColliderBuilder, SharedShape},
+  parry::shape::TypedShape,
+};
+use serde::{Deserialize, Serialize};
+
+// Subdivisions used when a ball or capsule has to become a polyhedron under non-uniform scale
+const ROUND_SUBDIVISIONS: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColliderShape {
+  // Exact surface, best for fixed terrain
+  TriMesh,
+  ConvexHull,
+  // Approximate concave shapes with several convex parts (VHACD), slow to build
+  ConvexDecomposition,
+  BoundingBall,
+  BoundingBox,
+  // Upright capsule around the bounding box
+  BoundingCapsule,
+}
+
+impl ColliderShape {
+  // Builds a collider for the geometry scaled the same way it is rendered
+  pub fn from_geometry(
+    &self,
+    geometry: &Geometry,
+    scale: &Vector3<f32>,
+  ) -> Option<ColliderBuilder> {
+    let points: Vec<Point3<f32>> = geometry
+      .vertices
+      .iter()
+      .map(|v| Point3::from(Vector3::from(*v).component_mul(scale)))
+      .collect();
+    if points.is_empty() {
+      return None;
+    }
+    // Parry panics on meshes without whole triangles or with indices past the last vertex
+    let count = points.len();
+    let triangles = || {
+      let indices = &geometry.indices;
+      if indices.is_empty()
+        || !indices.len().is_multiple_of(3)
+        || indices.iter().any(|&i| i as usize >= count)
+      {
+        return None;
+      }
+      Some(
+        indices
+          .chunks(3)
+          .map(|v| [v[0] as u32, v[1] as u32, v[2] as u32])
+          .collect::<Vec<_>>(),
+      )
+    };
+    let (mins, maxs) = points
+      .iter()
+      .fold((points[0].coords, points[0].coords), |(mins, maxs), p| {
+        (mins.inf(&p.coords), maxs.sup(&p.coords))
+      });
+    let center = (mins + maxs) / 2.;
+    let half: Vector3<f32> = (maxs - mins) / 2.;
+    let builder = match self {
+      Self::TriMesh => ColliderBuilder::trimesh(points, triangles()?),
+      Self::ConvexHull => ColliderBuilder::convex_hull(&points)?,
+      Self::ConvexDecomposition => ColliderBuilder::convex_decomposition(&points, &triangles()?),
+      Self::BoundingBall => {
+        let radius = points
+          .iter()
+          .map(|p| (p.coords - center).norm())
+          .fold(0., f32::max);
+        ColliderBuilder::ball(radius).translation(center)
+      }
+      Self::BoundingBox => ColliderBuilder::cuboid(half.x, half.y, half.z).translation(center),
+      Self::BoundingCapsule => {
+        let radius = half.x.max(half.z);
+        ColliderBuilder::capsule_y((half.y - radius).max(0.), radius).translation(center)
+      }
+    };
+    Some(builder)
+  }
+}
+
+// What an entity's collider was made from, so it can be rebuilt when the entity is rescaled
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ColliderSource {
+  // A shape given in mesh units with its offset from the body
+  Shape(SharedShape, Isometry3<f32>),
+  Geometry(ColliderShape),
+}
+
+impl ColliderSource {
+  pub fn scaled(
+    &self,
+    geometry: &Geometry,
+    scale: &Vector3<f32>,
+  ) -> Option<(SharedShape, Isometry3<f32>)> {
+    match self {
+      Self::Shape(shape, position) => {
+        let mut position = *position;
+        position.translation.vector.component_mul_assign(scale);
+        Some((scale_shape(shape, scale)?, position))
+      }
+      Self::Geometry(shape) => {
+        let builder = shape.from_geometry(geometry, scale)?;
+        Some((builder.shape, builder.position))
+      }
+    }
+  }
+}
+
+// Scales a shape along its local axes, `None` for shapes that can't be scaled or degenerate
+pub fn scale_shape(shape: &SharedShape, scale: &Vector3<f32>) -> Option<SharedShape> {
+  if *scale == Vector3::repeat(1.) {
+    return Some(shape.clone());
+  }
+  let scaled = match shape.as_typed_shape() {
+    TypedShape::Ball(ball) => ball
+      .scaled(scale, ROUND_SUBDIVISIONS)?
+      .either(SharedShape::new, SharedShape::new),
+    TypedShape::Capsule(capsule) => capsule
+      .scaled(scale, ROUND_SUBDIVISIONS)?
+      .either(SharedShape::new, SharedShape::new),
+    TypedShape::Cuboid(cuboid) => SharedShape::new(cuboid.scaled(scale)),
+    TypedShape::ConvexPolyhedron(poly) => SharedShape::new(poly.clone().scaled(scale)?),
+    TypedShape::TriMesh(mesh) => SharedShape::new(mesh.clone().scaled(scale)),
+    TypedShape::Triangle(triangle) => SharedShape::new(triangle.scaled(scale)),
+    TypedShape::Segment(segment) => SharedShape::new(segment.scaled(scale)),
+    // Exact only while the parts aren't rotated relative to the compound
+    TypedShape::Compound(compound) => SharedShape::compound(
+      compound
+        .shapes()
+        .iter()
+        .map(|(position, part)| {
+          let mut position = *position;
+          position.translation.vector.component_mul_assign(scale);
+          Some((position, scale_shape(part, scale)?))
+        })
+        .collect::<Option<_>>()?,
+    ),
+    _ => return None,
+  };
+  Some(scaled)
+}
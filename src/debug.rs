@@ -0,0 +1,449 @@
+use crate::renderer::{Color, DepthMode};
+use crate::{iter_to_array, Geometry, Viewport};
+use js_sys::Float32Array;
+use nalgebra::{Isometry3, Matrix4, Point3, Vector3};
+use rapier3d::pipeline::{DebugRenderBackend, DebugRenderMode, DebugRenderObject};
+use std::f32::consts::TAU;
+use wasm_bindgen::JsValue;
+use web_sys::{
+  gpu_buffer_usage, gpu_shader_stage, GpuBindGroup, GpuBindGroupDescriptor, GpuBindGroupEntry,
+  GpuBindGroupLayoutDescriptor, GpuBindGroupLayoutEntry, GpuBuffer, GpuBufferBinding,
+  GpuBufferBindingLayout, GpuBufferDescriptor, GpuColorTargetState, GpuCompareFunction,
+  GpuDepthStencilState, GpuDevice, GpuFragmentState, GpuPipelineLayoutDescriptor,
+  GpuPrimitiveState, GpuPrimitiveTopology, GpuRenderPassEncoder, GpuRenderPipeline,
+  GpuRenderPipelineDescriptor, GpuShaderModuleDescriptor, GpuTextureFormat, GpuVertexAttribute,
+  GpuVertexBufferLayout, GpuVertexFormat, GpuVertexState,
+};
+
+// position + rgba
+const VERTEX_SIZE: usize = 7;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DebugMode {
+  pub colliders: bool,
+  pub aabbs: bool,
+  pub contacts: bool,
+  pub velocities: bool,
+  pub normals: bool,
+  pub wireframe: bool,
+}
+
+impl DebugMode {
+  pub fn physics(&self) -> bool {
+    self.colliders || self.aabbs || self.contacts || self.velocities || self.normals
+  }
+  pub fn toggle_physics(&mut self) {
+    let on = !self.physics();
+    self.colliders = on;
+    self.aabbs = on;
+    self.contacts = on;
+    self.velocities = on;
+    self.normals = on;
+  }
+  pub fn toggle_wireframe(&mut self) {
+    self.wireframe = !self.wireframe;
+  }
+  pub fn rapier_mode(&self) -> DebugRenderMode {
+    let mut mode = DebugRenderMode::empty();
+    mode.set(DebugRenderMode::COLLIDER_SHAPES, self.colliders);
+    mode.set(DebugRenderMode::COLLIDER_AABBS, self.aabbs);
+    mode.set(DebugRenderMode::CONTACTS, self.contacts);
+    mode
+  }
+}
+
+#[derive(Default)]
+pub struct DebugLines {
+  vertices: Vec<f32>,
+  // Solid meshes are replaced by their edges in this batch
+  pub wireframe: bool,
+}
+
+impl DebugLines {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn clear(&mut self) {
+    self.vertices.clear();
+    self.wireframe = false;
+  }
+  pub fn vertices(&self) -> &[f32] {
+    &self.vertices
+  }
+  pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: Color) {
+    let Color {
+      r,
+      g,
+      b: blue,
+      a: alpha,
+    } = color;
+    self
+      .vertices
+      .extend_from_slice(&[a.x, a.y, a.z, r, g, blue, alpha]);
+    self
+      .vertices
+      .extend_from_slice(&[b.x, b.y, b.z, r, g, blue, alpha]);
+  }
+  pub fn edges(&mut self, geometry: &Geometry, model: &Matrix4<f32>, color: Color) {
+    for tri in geometry.indices.chunks(3) {
+      let [a, b, c] = [tri[0], tri[1], tri[2]]
+        .map(|i| model.transform_point(&Point3::from(geometry.vertices[i as usize])));
+      self.line(a, b, color);
+      self.line(b, c, color);
+      self.line(c, a, color);
+    }
+  }
+  pub fn normals(&mut self, geometry: &Geometry, model: &Matrix4<f32>, color: Color) {
+    // Normals skew under non-uniform scale unless they go through the inverse transpose
+    let linear = model.fixed_view::<3, 3>(0, 0);
+    let Some(normal_matrix) = linear.try_inverse().map(|m| m.transpose()) else {
+      return;
+    };
+    for (v, n) in geometry.vertices.iter().zip(geometry.normals()) {
+      let p = model.transform_point(&Point3::from(*v));
+      let length = (linear * n * 0.1).norm();
+      self.line(p, p + (normal_matrix * n).normalize() * length, color);
+    }
+  }
+}
+
+impl DebugRenderBackend for DebugLines {
+  fn draw_line(
+    &mut self,
+    _object: DebugRenderObject,
+    a: Point3<f32>,
+    b: Point3<f32>,
+    color: [f32; 4],
+  ) {
+    self.line(a, b, Color::hsla(color));
+  }
+}
+
+pub struct LineRenderer {
+  // One per depth mode, the lines go into whichever pass is drawing
+  standard: GpuRenderPipeline,
+  reverse_z: GpuRenderPipeline,
+  uniform_buffer: GpuBuffer,
+  bind_group: GpuBindGroup,
+  vertex_buffer: GpuBuffer,
+  capacity: usize,
+}
+
+impl LineRenderer {
+  pub fn new(device: &GpuDevice, format: GpuTextureFormat) -> Self {
+    let shader = device.create_shader_module(&GpuShaderModuleDescriptor::new(include_str!(
+      "shader_lines.wgsl"
+    )));
+    let position_attribute_description = GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 0);
+    let color_attribute_description =
+      GpuVertexAttribute::new(GpuVertexFormat::Float32x4, 4. * 3., 1);
+    let mut vertex_state = GpuVertexState::new(&shader);
+    vertex_state.entry_point("vs_main");
+    vertex_state.buffers(&iter_to_array(&[GpuVertexBufferLayout::new(
+      (4 * VERTEX_SIZE) as f64,
+      &iter_to_array(&[position_attribute_description, color_attribute_description]),
+    )]));
+    let mut fragment_state =
+      GpuFragmentState::new(&shader, &iter_to_array(&[GpuColorTargetState::new(format)]));
+    fragment_state.entry_point("fs_main");
+    let mut uniforms = GpuBindGroupLayoutEntry::new(0, gpu_shader_stage::VERTEX);
+    uniforms.buffer(&GpuBufferBindingLayout::new());
+    let bind_group_layout =
+      device.create_bind_group_layout(&GpuBindGroupLayoutDescriptor::new(&iter_to_array(&[
+        uniforms,
+      ])));
+    let layout =
+      device.create_pipeline_layout(&GpuPipelineLayoutDescriptor::new(&iter_to_array(&[
+        JsValue::from(&bind_group_layout),
+      ])));
+    // Lines are drawn on top of everything so colliders stay visible inside meshes
+    let pipeline = |depth_mode: DepthMode| {
+      device.create_render_pipeline(
+        GpuRenderPipelineDescriptor::new(&layout, &vertex_state)
+          .label("Debug line pipeline")
+          .fragment(&fragment_state)
+          .primitive(GpuPrimitiveState::new().topology(GpuPrimitiveTopology::LineList))
+          .depth_stencil(
+            GpuDepthStencilState::new(depth_mode.format())
+              .depth_compare(GpuCompareFunction::Always)
+              .depth_write_enabled(false),
+          ),
+      )
+    };
+    let uniform_buffer = device.create_buffer(&GpuBufferDescriptor::new(
+      64.,
+      gpu_buffer_usage::UNIFORM | gpu_buffer_usage::COPY_DST,
+    ));
+    let bind_group = device.create_bind_group(&GpuBindGroupDescriptor::new(
+      &iter_to_array(&[JsValue::from(&GpuBindGroupEntry::new(
+        0,
+        &GpuBufferBinding::new(&uniform_buffer),
+      ))]),
+      &bind_group_layout,
+    ));
+    let capacity = 4096 * VERTEX_SIZE;
+    Self {
+      vertex_buffer: Self::create_vertex_buffer(device, capacity),
+      standard: pipeline(DepthMode::Standard),
+      reverse_z: pipeline(DepthMode::ReverseZ),
+      uniform_buffer,
+      bind_group,
+      capacity,
+    }
+  }
+  fn create_vertex_buffer(device: &GpuDevice, capacity: usize) -> GpuBuffer {
+    device.create_buffer(&GpuBufferDescriptor::new(
+      (capacity * 4) as f64,
+      gpu_buffer_usage::VERTEX | gpu_buffer_usage::COPY_DST,
+    ))
+  }
+  // Returns the number of bytes uploaded
+  pub fn draw(
+    &mut self,
+    device: &GpuDevice,
+    pass_encoder: &GpuRenderPassEncoder,
+    lines: &DebugLines,
+    viewport: &Viewport,
+  ) -> usize {
+    if lines.vertices().is_empty() {
+      return 0;
+    }
+    // Relative to the eye like meshes, so lines far from the origin don't jitter
+    let eye = viewport.eye();
+    let mut vertices = lines.vertices().to_vec();
+    for vertex in vertices.chunks_mut(VERTEX_SIZE) {
+      for (position, eye) in vertex.iter_mut().zip(eye.iter()) {
+        *position = (*position as f64 - eye) as f32;
+      }
+    }
+    if vertices.len() > self.capacity {
+      self.capacity = vertices.len().next_power_of_two();
+      self.vertex_buffer.destroy();
+      self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+    }
+    let queue = device.queue();
+    queue.write_buffer_with_u32_and_buffer_source(
+      &self.uniform_buffer,
+      0,
+      &Float32Array::from(viewport.relative_view_proj().as_slice()),
+    );
+    queue.write_buffer_with_u32_and_buffer_source(
+      &self.vertex_buffer,
+      0,
+      &Float32Array::from(&vertices[..]),
+    );
+    pass_encoder.set_pipeline(match viewport.projection().depth_mode() {
+      DepthMode::Standard => &self.standard,
+      DepthMode::ReverseZ => &self.reverse_z,
+    });
+    pass_encoder.set_bind_group(0, Some(&self.bind_group));
+    pass_encoder.set_vertex_buffer(0, Some(&self.vertex_buffer));
+    pass_encoder.draw((vertices.len() / VERTEX_SIZE) as u32);
+    (vertices.len() + 16) * 4
+  }
+}
+
+enum Shape {
+  Line(Point3<f32>, Point3<f32>),
+  Aabb(Point3<f32>, Point3<f32>),
+  Sphere(Point3<f32>, f32),
+  Axes(Isometry3<f32>, f32),
+  Text(Point3<f32>, String, f32),
+}
+
+pub struct DebugItem {
+  shape: Shape,
+  color: Color,
+  seconds: f32,
+}
+
+impl DebugItem {
+  // Keep drawing the item for this many seconds instead of a single frame
+  pub fn lasting(&mut self, seconds: f32) -> &mut Self {
+    self.seconds = seconds;
+    self
+  }
+}
+
+// Immediate mode shapes, tessellated into the debug line batch once per frame
+#[derive(Default)]
+pub struct DebugDraw {
+  items: Vec<DebugItem>,
+}
+
+impl DebugDraw {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  fn push(&mut self, shape: Shape, color: Color) -> &mut DebugItem {
+    self.items.push(DebugItem {
+      shape,
+      color,
+      seconds: 0.,
+    });
+    self.items.last_mut().unwrap()
+  }
+  pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: Color) -> &mut DebugItem {
+    self.push(Shape::Line(a, b), color)
+  }
+  pub fn aabb(&mut self, mins: Point3<f32>, maxs: Point3<f32>, color: Color) -> &mut DebugItem {
+    self.push(Shape::Aabb(mins, maxs), color)
+  }
+  pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: Color) -> &mut DebugItem {
+    self.push(Shape::Sphere(center, radius), color)
+  }
+  pub fn axes(&mut self, iso: &Isometry3<f32>, length: f32) -> &mut DebugItem {
+    self.push(Shape::Axes(*iso, length), Color::rgb(1., 1., 1.))
+  }
+  pub fn text(
+    &mut self,
+    position: Point3<f32>,
+    text: &str,
+    size: f32,
+    color: Color,
+  ) -> &mut DebugItem {
+    self.push(Shape::Text(position, text.to_owned(), size), color)
+  }
+  // `dt` is the time the frame loop advanced, so lasting items don't expire while paused
+  pub fn flush(&mut self, lines: &mut DebugLines, viewport: &Viewport, dt: f32) {
+    let camera = viewport.view().rotation.inverse();
+    let (right, up) = (camera * Vector3::x(), camera * Vector3::y());
+    for item in self.items.iter() {
+      let color = item.color;
+      match &item.shape {
+        Shape::Line(a, b) => lines.line(*a, *b, color),
+        Shape::Aabb(mins, maxs) => {
+          let corner = |i: usize| {
+            Point3::new(
+              if i & 1 == 0 { mins.x } else { maxs.x },
+              if i & 2 == 0 { mins.y } else { maxs.y },
+              if i & 4 == 0 { mins.z } else { maxs.z },
+            )
+          };
+          for i in 0..8 {
+            for axis in [1, 2, 4] {
+              if i & axis == 0 {
+                lines.line(corner(i), corner(i | axis), color);
+              }
+            }
+          }
+        }
+        Shape::Sphere(center, radius) => {
+          let axes = [Vector3::x(), Vector3::y(), Vector3::z()];
+          for k in 0..3 {
+            let (u, v) = (axes[k], axes[(k + 1) % 3]);
+            let point = |i: usize| {
+              let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+              center + (u * angle.cos() + v * angle.sin()) * *radius
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+              lines.line(point(i), point(i + 1), color);
+            }
+          }
+        }
+        Shape::Axes(iso, length) => {
+          let origin = Point3::from(iso.translation.vector);
+          for (axis, color) in [
+            (Vector3::x(), Color::rgb(1., 0., 0.)),
+            (Vector3::y(), Color::rgb(0., 1., 0.)),
+            (Vector3::z(), Color::rgb(0., 0., 1.)),
+          ] {
+            lines.line(origin, origin + iso.rotation * axis * *length, color);
+          }
+        }
+        Shape::Text(position, text, size) => {
+          let width = size / 2.;
+          let advance = width * 1.5;
+          let start = -(text.chars().count() as f32 * advance - advance + width) / 2.;
+          for (i, c) in text.chars().enumerate() {
+            let origin = position + right * (start + i as f32 * advance) - up * (size / 2.);
+            let at = |(x, y): (f32, f32)| origin + right * (x * width / 2.) + up * (y * size / 2.);
+            for segment in glyph(c).chars().filter_map(segment) {
+              lines.line(at(segment.0), at(segment.1), color);
+            }
+          }
+        }
+      }
+    }
+    self.items.retain_mut(|item| {
+      item.seconds -= dt;
+      item.seconds > 0.
+    });
+  }
+}
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+// Endpoints of a sixteen segment display cell on a 3x3 grid
+fn segment(s: char) -> Option<((f32, f32), (f32, f32))> {
+  Some(match s {
+    'a' => ((0., 2.), (1., 2.)),
+    'A' => ((1., 2.), (2., 2.)),
+    'b' => ((2., 2.), (2., 1.)),
+    'c' => ((2., 1.), (2., 0.)),
+    'D' => ((2., 0.), (1., 0.)),
+    'd' => ((1., 0.), (0., 0.)),
+    'e' => ((0., 0.), (0., 1.)),
+    'f' => ((0., 1.), (0., 2.)),
+    'g' => ((0., 1.), (1., 1.)),
+    'G' => ((1., 1.), (2., 1.)),
+    'h' => ((0., 2.), (1., 1.)),
+    'i' => ((1., 2.), (1., 1.)),
+    'j' => ((2., 2.), (1., 1.)),
+    'k' => ((1., 1.), (0., 0.)),
+    'l' => ((1., 1.), (1., 0.)),
+    'm' => ((1., 1.), (2., 0.)),
+    _ => return None,
+  })
+}
+
+fn glyph(c: char) -> &'static str {
+  match c.to_ascii_uppercase() {
+    '0' => "aAbcdDefjk",
+    '1' => "bcj",
+    '2' => "aAbgGedD",
+    '3' => "aAbcdDG",
+    '4' => "fgGbc",
+    '5' | 'S' => "aAfgGcdD",
+    '6' => "aAfedDcgG",
+    '7' => "aAbc",
+    '8' => "aAbcdDefgG",
+    '9' => "aAbcdDfgG",
+    'A' => "aAbcefgG",
+    'B' => "aAbcdDGil",
+    'C' => "aAefdD",
+    'D' => "aAbcdDil",
+    'E' => "aAefdDg",
+    'F' => "aAefg",
+    'G' => "aAefdDcG",
+    'H' => "efbcgG",
+    'I' => "aAdDil",
+    'J' => "bcdDe",
+    'K' => "efgjm",
+    'L' => "efdD",
+    'M' => "efbchj",
+    'N' => "efbchm",
+    'O' => "aAbcdDef",
+    'P' => "aAbefgG",
+    'Q' => "aAbcdDefm",
+    'R' => "aAbefgGm",
+    'T' => "aAil",
+    'U' => "bcdDef",
+    'V' => "efkj",
+    'W' => "efbckm",
+    'X' => "hjkm",
+    'Y' => "hjl",
+    'Z' => "aAjkdD",
+    '-' => "gG",
+    '+' => "gGil",
+    '=' => "gGdD",
+    '_' => "dD",
+    '/' => "jk",
+    '(' => "jm",
+    ')' => "hk",
+    '*' => "gGhijklm",
+    '.' | ',' => "l",
+    ':' => "il",
+    _ => "",
+  }
+}
@@ -0,0 +1,166 @@
+use nalgebra::Vector3;
+use rapier3d::{
+  crossbeam::channel::{unbounded, Receiver},
+  geometry::{ColliderHandle, CollisionEvent, ContactForceEvent},
+  pipeline::ChannelEventCollector,
+};
+use std::collections::VecDeque;
+
+const MAX_QUEUED: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContactKind {
+  Started {
+    sensor: bool,
+  },
+  Stopped {
+    sensor: bool,
+    removed: bool,
+  },
+  Force {
+    total: Vector3<f32>,
+    magnitude: f32,
+    max_direction: Vector3<f32>,
+    max_magnitude: f32,
+  },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContactEvent {
+  pub a: String,
+  pub b: String,
+  pub kind: ContactKind,
+}
+
+impl ContactEvent {
+  pub fn involves(&self, name: &str) -> bool {
+    self.a == name || self.b == name
+  }
+  pub fn between(&self, a: &str, b: &str) -> bool {
+    (self.a == a && self.b == b) || (self.a == b && self.b == a)
+  }
+  // The entity on the other side of the contact from `name`
+  pub fn other(&self, name: &str) -> Option<&str> {
+    if self.a == name {
+      Some(&self.b)
+    } else if self.b == name {
+      Some(&self.a)
+    } else {
+      None
+    }
+  }
+  pub fn started(&self) -> bool {
+    matches!(self.kind, ContactKind::Started { .. })
+  }
+  pub fn stopped(&self) -> bool {
+    matches!(self.kind, ContactKind::Stopped { .. })
+  }
+}
+
+struct Listener {
+  a: String,
+  b: Option<String>,
+  callback: Box<dyn FnMut(&ContactEvent)>,
+}
+
+impl Listener {
+  fn matches(&self, event: &ContactEvent) -> bool {
+    match &self.b {
+      Some(b) => event.between(&self.a, b),
+      None => event.involves(&self.a),
+    }
+  }
+}
+
+// Collects rapier events during a step and hands them out by entity name afterwards
+pub struct ContactEvents {
+  collector: ChannelEventCollector,
+  collisions: Receiver<CollisionEvent>,
+  forces: Receiver<ContactForceEvent>,
+  queue: VecDeque<ContactEvent>,
+  listeners: Vec<Listener>,
+}
+
+impl Default for ContactEvents {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ContactEvents {
+  pub fn new() -> Self {
+    let (collision_send, collisions) = unbounded();
+    let (force_send, forces) = unbounded();
+    Self {
+      collector: ChannelEventCollector::new(collision_send, force_send),
+      collisions,
+      forces,
+      queue: VecDeque::new(),
+      listeners: Vec::new(),
+    }
+  }
+  pub fn collector(&self) -> &ChannelEventCollector {
+    &self.collector
+  }
+  // Turns the raw events of the last step into named events, `name` maps a collider to its entity
+  pub fn dispatch(
+    &mut self,
+    name: impl Fn(ColliderHandle, ColliderHandle) -> Option<(String, String)>,
+  ) {
+    let mut events = Vec::new();
+    while let Ok(event) = self.collisions.try_recv() {
+      let Some((a, b)) = name(event.collider1(), event.collider2()) else {
+        continue;
+      };
+      let kind = match event {
+        CollisionEvent::Started(_, _, _) => ContactKind::Started {
+          sensor: event.sensor(),
+        },
+        CollisionEvent::Stopped(_, _, _) => ContactKind::Stopped {
+          sensor: event.sensor(),
+          removed: event.removed(),
+        },
+      };
+      events.push(ContactEvent { a, b, kind });
+    }
+    while let Ok(event) = self.forces.try_recv() {
+      let Some((a, b)) = name(event.collider1, event.collider2) else {
+        continue;
+      };
+      let kind = ContactKind::Force {
+        total: event.total_force,
+        magnitude: event.total_force_magnitude,
+        max_direction: event.max_force_direction,
+        max_magnitude: event.max_force_magnitude,
+      };
+      events.push(ContactEvent { a, b, kind });
+    }
+    for event in events {
+      for listener in self.listeners.iter_mut() {
+        if listener.matches(&event) {
+          (listener.callback)(&event);
+        }
+      }
+      // Nobody may be polling, so keep only the most recent events around
+      if self.queue.len() == MAX_QUEUED {
+        self.queue.pop_front();
+      }
+      self.queue.push_back(event);
+    }
+  }
+  pub fn drain(&mut self) -> impl Iterator<Item = ContactEvent> + '_ {
+    self.queue.drain(..)
+  }
+  pub fn subscribe(
+    &mut self,
+    a: &str,
+    b: Option<&str>,
+    callback: impl FnMut(&ContactEvent) + 'static,
+  ) {
+    self.listeners.push(Listener {
+      a: a.to_owned(),
+      b: b.map(str::to_owned),
+      callback: Box::new(callback),
+    });
+  }
+}
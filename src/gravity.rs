@@ -0,0 +1,49 @@
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Gravity {
+  #[default]
+  None,
+  Uniform(Vector3<f32>),
+  // Pulls toward `center`, with `acceleration` at `radius` falling off with the inverse square
+  // outside of it and linearly inside, like a planet of uniform density
+  Radial {
+    center: Point3<f32>,
+    acceleration: f32,
+    radius: f32,
+  },
+}
+
+impl Gravity {
+  pub fn radial(center: Point3<f32>, acceleration: f32, radius: f32) -> Self {
+    Self::Radial {
+      center,
+      acceleration,
+      radius,
+    }
+  }
+  pub fn acceleration_at(&self, point: &Point3<f32>) -> Vector3<f32> {
+    match *self {
+      Self::None => Vector3::zeros(),
+      Self::Uniform(acceleration) => acceleration,
+      Self::Radial {
+        center,
+        acceleration,
+        radius,
+      } => {
+        let to_center = center - point;
+        let distance = to_center.magnitude();
+        if distance <= f32::EPSILON {
+          return Vector3::zeros();
+        }
+        let falloff = if distance < radius {
+          distance / radius
+        } else {
+          (radius / distance).powi(2)
+        };
+        to_center / distance * acceleration * falloff
+      }
+    }
+  }
+}
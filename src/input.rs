@@ -0,0 +1,238 @@
+use fluid::add_event_and_forget;
+use gloo_utils::window;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::rc::Rc;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::KeyboardEvent;
+
+// Keys are physical `KeyboardEvent.code` values like "KeyW" or "Space", so bindings stay
+// where they are on any layout and aren't affected by caps lock or shift
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Axis {
+  pub negative: Vec<String>,
+  pub positive: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bindings {
+  pub actions: BTreeMap<String, Vec<String>>,
+  pub axes: BTreeMap<String, Axis>,
+}
+
+impl Bindings {
+  // Adds a key to the action, keeping any it already has
+  pub fn bind(mut self, action: &str, code: &str) -> Self {
+    self
+      .actions
+      .entry(action.to_owned())
+      .or_default()
+      .push(code.to_owned());
+    self
+  }
+  pub fn bind_axis(mut self, axis: &str, negative: &str, positive: &str) -> Self {
+    let axis = self.axes.entry(axis.to_owned()).or_default();
+    axis.negative.push(negative.to_owned());
+    axis.positive.push(positive.to_owned());
+    self
+  }
+  // Replaces all keys of an action, e.g. from a rebinding menu
+  pub fn rebind(&mut self, action: &str, codes: &[&str]) {
+    self.actions.insert(
+      action.to_owned(),
+      codes.iter().map(|code| code.to_string()).collect(),
+    );
+  }
+  pub fn unbind(&mut self, action: &str) {
+    self.actions.remove(action);
+    self.axes.remove(action);
+  }
+  // Takes every action and axis `other` has keys for, leaving the rest as they are
+  pub fn merge(mut self, other: Bindings) -> Self {
+    self.actions.extend(other.actions);
+    self.axes.extend(other.axes);
+    self
+  }
+  pub fn to_json(&self) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(self)
+  }
+  pub fn from_json(json: &str) -> serde_json::Result<Self> {
+    serde_json::from_str(json)
+  }
+  // Bindings saved in local storage under `key`, `None` if there are none or they don't parse
+  pub fn load(key: &str) -> Option<Self> {
+    let json = window().local_storage().ok()??.get_item(key).ok()??;
+    Self::from_json(&json).ok()
+  }
+  pub fn save(&self, key: &str) -> Result<(), JsValue> {
+    let json = self
+      .to_json()
+      .map_err(|err| JsValue::from_str(&err.to_string()))?;
+    window()
+      .local_storage()?
+      .ok_or_else(|| JsValue::from_str("Local storage is unavailable"))?
+      .set_item(key, &json)
+  }
+}
+
+// Tracks which keys are held, and which went down or up since the last `end_frame`
+pub struct Input {
+  bindings: Bindings,
+  held: HashSet<String>,
+  pressed: HashSet<String>,
+  released: HashSet<String>,
+}
+
+impl Input {
+  pub fn new(bindings: Bindings) -> Self {
+    Self {
+      bindings,
+      held: HashSet::new(),
+      pressed: HashSet::new(),
+      released: HashSet::new(),
+    }
+  }
+  // Feeds the window's keyboard events into `input`
+  pub fn listen(input: Rc<RefCell<Self>>) {
+    let window = window();
+    {
+      let input = input.clone();
+      add_event_and_forget(&window, "keydown", move |e| {
+        let code = e.dyn_into::<KeyboardEvent>().unwrap().code();
+        input.borrow_mut().key_down(&code);
+      });
+    }
+    {
+      let input = input.clone();
+      add_event_and_forget(&window, "keyup", move |e| {
+        let code = e.dyn_into::<KeyboardEvent>().unwrap().code();
+        input.borrow_mut().key_up(&code);
+      });
+    }
+    // Keys released while the window is in the background never send a keyup
+    add_event_and_forget(&window, "blur", move |_| {
+      input.borrow_mut().release_all();
+    });
+  }
+  pub fn bindings(&self) -> &Bindings {
+    &self.bindings
+  }
+  pub fn bindings_mut(&mut self) -> &mut Bindings {
+    &mut self.bindings
+  }
+  pub fn key_down(&mut self, code: &str) {
+    // Auto repeat sends more keydowns without a keyup in between
+    if self.held.insert(code.to_owned()) {
+      self.pressed.insert(code.to_owned());
+    }
+  }
+  pub fn key_up(&mut self, code: &str) {
+    if self.held.remove(code) {
+      self.released.insert(code.to_owned());
+    }
+  }
+  pub fn release_all(&mut self) {
+    self.released.extend(self.held.drain());
+  }
+  fn any(&self, action: &str, keys: &HashSet<String>) -> bool {
+    self
+      .bindings
+      .actions
+      .get(action)
+      .is_some_and(|codes| codes.iter().any(|code| keys.contains(code)))
+  }
+  pub fn held(&self, action: &str) -> bool {
+    self.any(action, &self.held)
+  }
+  pub fn pressed(&self, action: &str) -> bool {
+    self.any(action, &self.pressed)
+  }
+  pub fn released(&self, action: &str) -> bool {
+    self.any(action, &self.released)
+  }
+  // -1, 0 or 1; both sides held cancel out
+  pub fn axis(&self, axis: &str) -> f32 {
+    let Some(axis) = self.bindings.axes.get(axis) else {
+      return 0.;
+    };
+    let side = |codes: &[String]| codes.iter().any(|code| self.held.contains(code)) as i32;
+    (side(&axis.positive) - side(&axis.negative)) as f32
+  }
+  pub fn end_frame(&mut self) {
+    self.pressed.clear();
+    self.released.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn input() -> Input {
+    Input::new(
+      Bindings::default()
+        .bind("jump", "Space")
+        .bind("jump", "KeyJ")
+        .bind_axis("move_right", "KeyA", "KeyD"),
+    )
+  }
+
+  #[test]
+  fn pressed_and_released_last_one_frame() {
+    let mut input = input();
+    input.key_down("Space");
+    assert!(input.held("jump") && input.pressed("jump") && !input.released("jump"));
+    input.end_frame();
+    assert!(input.held("jump") && !input.pressed("jump"));
+    // Auto repeat isn't a new press
+    input.key_down("Space");
+    assert!(!input.pressed("jump"));
+    input.key_up("Space");
+    assert!(!input.held("jump") && input.released("jump"));
+    input.end_frame();
+    assert!(!input.released("jump"));
+  }
+
+  #[test]
+  fn any_bound_key_triggers_the_action() {
+    let mut input = input();
+    input.key_down("KeyJ");
+    assert!(input.pressed("jump"));
+    assert!(!input.held("unbound"));
+    input.release_all();
+    assert!(!input.held("jump") && input.released("jump"));
+  }
+
+  #[test]
+  fn opposite_sides_of_an_axis_cancel() {
+    let mut input = input();
+    assert_eq!(input.axis("move_right"), 0.);
+    input.key_down("KeyD");
+    assert_eq!(input.axis("move_right"), 1.);
+    input.key_down("KeyA");
+    assert_eq!(input.axis("move_right"), 0.);
+    input.key_up("KeyD");
+    assert_eq!(input.axis("move_right"), -1.);
+  }
+
+  #[test]
+  fn bindings_round_trip_through_json() {
+    let mut bindings = input().bindings().clone();
+    bindings.rebind("jump", &["KeyK"]);
+    let json = bindings.to_json().unwrap();
+    assert_eq!(Bindings::from_json(&json).unwrap(), bindings);
+  }
+
+  #[test]
+  fn merged_bindings_only_replace_what_they_bind() {
+    let saved = Bindings::default().bind("jump", "KeyK");
+    let bindings = input().bindings().clone().merge(saved);
+    let mut input = Input::new(bindings);
+    input.key_down("Space");
+    assert!(!input.held("jump"));
+    input.key_down("KeyK");
+    input.key_down("KeyD");
+    assert!(input.held("jump") && input.axis("move_right") == 1.);
+  }
+}
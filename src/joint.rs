@@ -0,0 +1,166 @@
+use nalgebra::{Point3, Unit, Vector3};
+use rapier3d::dynamics::{
+  FixedJointBuilder, GenericJoint, JointAxis, PrismaticJointBuilder, RevoluteJointBuilder,
+  RopeJointBuilder, SphericalJointBuilder, SpringJointBuilder,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JointKind {
+  Fixed,
+  // Hinge around the axis, in the local frame of the first entity
+  Revolute(Unit<Vector3<f32>>),
+  // Slider along the axis, in the local frame of the first entity
+  Prismatic(Unit<Vector3<f32>>),
+  Spherical,
+  Rope {
+    max_distance: f32,
+  },
+  Spring {
+    rest_length: f32,
+    stiffness: f32,
+    damping: f32,
+  },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Motor {
+  Velocity {
+    target: f32,
+    factor: f32,
+  },
+  Position {
+    target: f32,
+    stiffness: f32,
+    damping: f32,
+  },
+}
+
+// Describes how two entities are connected; limits and motors act on the joint's free axis,
+// angles in radians for revolute and spherical joints and distances for prismatic ones. Ropes
+// and springs already drive their distance with them, so they take neither
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Joint {
+  pub kind: JointKind,
+  pub anchor_a: Point3<f32>,
+  pub anchor_b: Point3<f32>,
+  pub limits: Option<[f32; 2]>,
+  pub motor: Option<Motor>,
+  pub max_force: Option<f32>,
+  pub contacts: bool,
+}
+
+impl Joint {
+  pub fn new(kind: JointKind) -> Self {
+    Self {
+      kind,
+      anchor_a: Point3::origin(),
+      anchor_b: Point3::origin(),
+      limits: None,
+      motor: None,
+      max_force: None,
+      contacts: false,
+    }
+  }
+  pub fn fixed() -> Self {
+    Self::new(JointKind::Fixed)
+  }
+  pub fn revolute(axis: Vector3<f32>) -> Self {
+    Self::new(JointKind::Revolute(Unit::new_normalize(axis)))
+  }
+  pub fn prismatic(axis: Vector3<f32>) -> Self {
+    Self::new(JointKind::Prismatic(Unit::new_normalize(axis)))
+  }
+  pub fn spherical() -> Self {
+    Self::new(JointKind::Spherical)
+  }
+  pub fn rope(max_distance: f32) -> Self {
+    Self::new(JointKind::Rope { max_distance })
+  }
+  pub fn spring(rest_length: f32, stiffness: f32, damping: f32) -> Self {
+    Self::new(JointKind::Spring {
+      rest_length,
+      stiffness,
+      damping,
+    })
+  }
+  // Attachment points in the local frames of the first and second entity
+  pub fn anchors(mut self, a: Point3<f32>, b: Point3<f32>) -> Self {
+    self.anchor_a = a;
+    self.anchor_b = b;
+    self
+  }
+  pub fn limits(mut self, min: f32, max: f32) -> Self {
+    self.limits = Some([min, max]);
+    self
+  }
+  pub fn motor(mut self, motor: Motor) -> Self {
+    self.motor = Some(motor);
+    self
+  }
+  pub fn max_force(mut self, max_force: f32) -> Self {
+    self.max_force = Some(max_force);
+    self
+  }
+  // Whether the connected entities still collide with each other
+  pub fn contacts(mut self, enabled: bool) -> Self {
+    self.contacts = enabled;
+    self
+  }
+  fn axes(&self) -> &'static [JointAxis] {
+    match self.kind {
+      JointKind::Fixed => &[],
+      JointKind::Revolute(_) => &[JointAxis::AngX],
+      JointKind::Spherical => &[JointAxis::AngX, JointAxis::AngY, JointAxis::AngZ],
+      JointKind::Prismatic(_) => &[JointAxis::LinX],
+      JointKind::Rope { .. } | JointKind::Spring { .. } => &[],
+    }
+  }
+  // `None` for a rope or spring with limits, a motor or a max force, they would replace its
+  // length or spring instead
+  pub fn build(&self) -> Option<GenericJoint> {
+    let driven = self.limits.is_some() || self.motor.is_some() || self.max_force.is_some();
+    if driven && matches!(self.kind, JointKind::Rope { .. } | JointKind::Spring { .. }) {
+      return None;
+    }
+    let mut joint: GenericJoint = match self.kind {
+      JointKind::Fixed => FixedJointBuilder::new().build().into(),
+      JointKind::Revolute(axis) => RevoluteJointBuilder::new(axis).build().into(),
+      JointKind::Prismatic(axis) => PrismaticJointBuilder::new(axis).build().into(),
+      JointKind::Spherical => SphericalJointBuilder::new().build().into(),
+      JointKind::Rope { max_distance } => RopeJointBuilder::new(max_distance).build().into(),
+      JointKind::Spring {
+        rest_length,
+        stiffness,
+        damping,
+      } => SpringJointBuilder::new(rest_length, stiffness, damping)
+        .build()
+        .into(),
+    };
+    joint
+      .set_local_anchor1(self.anchor_a)
+      .set_local_anchor2(self.anchor_b)
+      .set_contacts_enabled(self.contacts);
+    for axis in self.axes() {
+      if let Some(limits) = self.limits {
+        joint.set_limits(*axis, limits);
+      }
+      match self.motor {
+        Some(Motor::Velocity { target, factor }) => {
+          joint.set_motor_velocity(*axis, target, factor);
+        }
+        Some(Motor::Position {
+          target,
+          stiffness,
+          damping,
+        }) => {
+          joint.set_motor_position(*axis, target, stiffness, damping);
+        }
+        None => {}
+      }
+      if let Some(max_force) = self.max_force {
+        joint.set_motor_max_force(*axis, max_force);
+      }
+    }
+    Some(joint)
+  }
+}
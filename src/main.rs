@@ -1,36 +1,69 @@
+mod camera;
+mod capture;
+mod character;
+mod collider;
+mod debug;
+mod events;
 mod game;
+mod gravity;
+mod input;
+mod joint;
 mod mesh;
-mod movement;
+mod path;
+mod query;
 mod renderer;
 mod scene;
+mod snapshot;
+mod stats;
+mod target;
 mod viewport;
 mod world;
 
+pub use camera::{
+  CameraController, CameraInput, Chase, FirstPerson, Follow, FreeFly, Inherit, Orbit,
+};
+pub use capture::{Image, ZipWriter};
+pub use character::{Character, CharacterConfig};
+pub use collider::ColliderShape;
+pub use debug::{DebugDraw, DebugLines, DebugMode};
+pub use events::{ContactEvent, ContactKind};
 pub use game::Game;
+pub use gravity::Gravity;
+pub use input::{Axis, Bindings, Input};
+pub use joint::{Joint, JointKind, Motor};
 pub use mesh::{Geometry, Material, Mesh};
-use movement::Movement;
+pub use path::{CameraPath, Easing, Interpolation, Keyframe};
+pub use query::{PointHit, RayHit, ShapeHit};
 use renderer::Color;
-pub use renderer::Renderer;
+pub use renderer::{DepthMode, Renderer};
 pub use scene::Scene;
-pub use viewport::Viewport;
+pub use snapshot::Recording;
+pub use stats::Stats;
+pub use target::RenderTarget;
+pub use viewport::{Projection, Region, Viewport};
 use world::World;
 
 use nalgebra::Vector;
 
 use fluid::{add_event_and_forget, on_animation_frame, Context};
 use fluid_macro::html;
-use genmesh::generators::{Cube, IcoSphere};
+use genmesh::generators::{Cube, IcoSphere, Plane};
 use gloo_console::log;
 use gloo_utils::{body, window as gloo_window};
 use js_sys::Array;
 use wasm_bindgen::prelude::*;
-use web_sys::{KeyboardEvent, MouseEvent, WheelEvent};
+use web_sys::{MouseEvent, WheelEvent};
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use rapier3d::prelude::*;
 
+const RECORD_FRAMES: u32 = 120;
+const RECORD_BYTES: u64 = 512 << 20;
+// Local storage entry holding the key bindings
+const BINDINGS_KEY: &str = "bindings";
+
 pub fn iter_to_array<T>(iterable: impl IntoIterator<Item = T>) -> Array
 where
   T: Into<JsValue>,
@@ -38,6 +71,35 @@ where
   iterable.into_iter().map(|v| v.into()).collect::<Array>()
 }
 
+// Flies in from space down to the player's starting point
+fn intro() -> CameraPath {
+  use std::f32::consts::PI;
+  CameraPath::new(Interpolation::CatmullRom)
+    .keyframe(
+      0.,
+      point![0., 1500., 3000.],
+      point![0., -1010., 0.],
+      PI * 0.25,
+    )
+    .keyframe(3., point![800., 300., 800.], point![0., -10., 0.], PI * 0.3)
+    .keyframe(6., point![40., 20., 40.], point![0., 2., 0.], PI * 0.4)
+    .keyframe(8., point![0., 4., 12.], point![0., 2., 0.], PI * 0.4)
+    .easing(Easing::EaseInOut)
+}
+
+fn show_unavailable() -> Result<(), JsValue> {
+  let ui = html! {
+      div class="overlay shown" {
+          div class="unsupported" {
+              h1 { "WebGPU unavailable" }
+              p { "This demo needs a browser with WebGPU enabled, such as a recent Chrome or Edge." }
+          }
+      }
+  };
+  body().append_child(&ui)?;
+  Ok(())
+}
+
 fn main() {
   wasm_bindgen_futures::spawn_local(async move {
     async_main().await.unwrap_or_else(|err| {
@@ -47,10 +109,35 @@ fn main() {
 }
 
 async fn async_main() -> Result<(), JsValue> {
-  let renderer = Renderer::new().await?;
-  let viewport = Viewport::new(renderer.canvas());
+  let renderer = match Renderer::new().await {
+    Ok(renderer) => renderer,
+    Err(err) => {
+      show_unavailable()?;
+      return Err(err);
+    }
+  };
+  let mut viewport = Viewport::new(renderer.canvas());
+  viewport.set_follow_options(Follow {
+    look_ahead: 0.1,
+    ..Default::default()
+  });
+  viewport.play(intro(), 1.5);
   let ctx = Context::new();
   let viewport = Rc::new(RefCell::new(viewport));
+  // Top-down picture-in-picture of the player's surroundings
+  let minimap = {
+    let mut minimap = Viewport::new(renderer.canvas());
+    minimap.set_region(Region::new(0.74, 0.02, 0.24, 0.3));
+    minimap.set_projection(Projection::InfinitePerspective {
+      fov: std::f32::consts::PI * 0.25,
+      near: 1.,
+    });
+    let mut overhead = Orbit::new(80.);
+    overhead.pitch = -std::f32::consts::FRAC_PI_2 + 0.01;
+    minimap.set_controller(overhead);
+    Rc::new(RefCell::new(minimap))
+  };
+  let show_minimap = Rc::new(Cell::new(false));
   let mut scene = Scene::new();
 
   body().append_child(renderer.canvas())?;
@@ -104,18 +191,20 @@ async fn async_main() -> Result<(), JsValue> {
     )
     .await?;
 
-    let body = RigidBodyBuilder::dynamic()
-      .sleeping(true)
+    let body = RigidBodyBuilder::kinematic_position_based()
       .translation(vector![0., 2., 0.])
-      .additional_mass(1.)
-      .linear_damping(10.)
       .build();
 
-    scene.add("sphere", mesh, body);
+    scene
+      .add_w_scale_shape("sphere", mesh, body, ColliderShape::BoundingBall, 1.)
+      .ok_or_else(|| JsValue::from_str("Couldn't build the sphere collider"))?;
   }
 
   // Implement scene node
-  World::new(&renderer, &mut scene).await?;
+  let world = World::new(&renderer, &mut scene).await?;
+  scene.set_gravity(world.gravity());
+  // The cube has no collider yet, so it would sink straight through the planet
+  scene.set_body_gravity("cube", Some(Gravity::None));
 
   {
     let geo = Geometry::from_genmesh(&IcoSphere::subdivide(3));
@@ -133,6 +222,41 @@ async fn async_main() -> Result<(), JsValue> {
     let ball = ColliderBuilder::ball(1.).build();
 
     scene.add_w_scale_collider("vertex_cube", mesh, body, ball, 1.);
+    scene.on_contact("vertex_cube", Some("lithosphere"), |event| {
+      if event.started() {
+        log!("vertex_cube touched the lithosphere");
+      }
+    });
+  }
+
+  // A monitor next to the start showing the player from the side
+  let monitor = RenderTarget::new(&renderer, 512, 512, false);
+  let mut security_camera = {
+    let mut camera = Viewport::new(renderer.canvas());
+    camera.resize_to(monitor.width(), monitor.height());
+    let mut side = Orbit::new(12.);
+    side.yaw = std::f32::consts::FRAC_PI_2;
+    side.pitch = -0.4;
+    camera.set_controller(side);
+    camera
+  };
+  {
+    let geo = Geometry::from_genmesh(&Plane::new());
+    let coordinates = geo
+      .vertices
+      .iter()
+      .map(|v| [(v[0] + 1.) / 2., 1. - (v[1] + 1.) / 2.])
+      .collect();
+    let mesh = Mesh::new(
+      &renderer,
+      &geo,
+      &Material::render_target(&monitor, coordinates),
+    )
+    .await?;
+    let body = RigidBodyBuilder::fixed()
+      .translation(vector![0., 5., -8.])
+      .build();
+    scene.add_w_scale("monitor", mesh, body, 3.);
   }
 
   let renderer = Rc::new(RefCell::new(renderer));
@@ -143,6 +267,7 @@ async fn async_main() -> Result<(), JsValue> {
     let viewport = viewport.clone();
     let w1 = game.clone();
     let w2 = game.clone();
+    let r1 = renderer.clone();
     let ui = html! {
         div class=[ctx, [game] -> &format!("overlay {}",if game.paused() {"shown"} else {""})] {
             div class="pause-menu" {
@@ -167,6 +292,16 @@ async fn async_main() -> Result<(), JsValue> {
                          "Go fullscreeen"
                      }
                  ]}
+                 button
+                 @click=(move |_| {
+                     let image = r1.borrow_mut().capture_frame();
+                     wasm_bindgen_futures::spawn_local(async move {
+                         if let Err(err) = image.await.and_then(|image| image.download("screenshot.png")) {
+                             log!("Couldn't save screenshot", err);
+                         }
+                     });
+                 })
+                 { "Save screenshot" }
              }
             }
         }
@@ -174,7 +309,12 @@ async fn async_main() -> Result<(), JsValue> {
     body().append_child(&ui)?;
   }
 
-  let movement = Rc::new(RefCell::new(Movement { dx: 0, dy: 0 }));
+  let stats = Rc::new(RefCell::new(Stats::new(&ctx)));
+  stats.borrow().mount(&ctx, &body())?;
+
+  let debug_mode = Rc::new(RefCell::new(DebugMode::default()));
+  let mut debug_lines = DebugLines::new();
+  let debug = Rc::new(RefCell::new(DebugDraw::new()));
 
   {
     let viewport = viewport.clone();
@@ -192,86 +332,232 @@ async fn async_main() -> Result<(), JsValue> {
       let me = e.dyn_into::<MouseEvent>().unwrap();
       viewport
         .borrow_mut()
-        .update_rot(me.movement_x(), me.movement_y(), 1.);
+        .update_rot(me.movement_x(), me.movement_y());
     });
   }
-  let next_delta = |prev, next| {
-    let val = prev + next;
-    if val >= 1 {
-      1
-    } else if val <= -1 {
-      -1
-    } else {
-      0
-    }
-  };
   {
-    let movement = movement.clone();
-    add_event_and_forget(&gloo_window(), "keydown", move |e| {
-      let key = e.dyn_into::<KeyboardEvent>().unwrap().key();
-      match key.as_str() {
-        "w" => {
-          let current_dy = movement.borrow().dy;
-          movement.borrow_mut().dy = next_delta(current_dy, 1);
-        }
-        "s" => {
-          let current_dy = movement.borrow().dy;
-          movement.borrow_mut().dy = next_delta(current_dy, -1);
-        }
-        "a" => {
-          let current_dx = movement.borrow().dx;
-          movement.borrow_mut().dx = next_delta(current_dx, -1);
-        }
-        "d" => {
-          let current_dx = movement.borrow().dx;
-          movement.borrow_mut().dx = next_delta(current_dx, 1);
-        }
-        _ => {}
-      }
+    let renderer = renderer.clone();
+    let minimap = minimap.clone();
+    // Registered after the game's listener, so the canvas already has its new size
+    add_event_and_forget(&gloo_window(), "resize", move |_| {
+      minimap.borrow_mut().resize(renderer.borrow().canvas());
     });
   }
+  // Clicks are picked in the frame loop, where the scene lives
+  let click = Rc::new(Cell::new(None));
   {
-    let movement = movement.clone();
-    add_event_and_forget(&gloo_window(), "keyup", move |e| {
-      let key = e.dyn_into::<KeyboardEvent>().unwrap().key();
-      match key.as_str() {
-        "w" => {
-          let current_dy = movement.borrow().dy;
-          movement.borrow_mut().dy = next_delta(current_dy, -1);
-        }
-        "s" => {
-          let current_dy = movement.borrow().dy;
-          movement.borrow_mut().dy = next_delta(current_dy, 1);
-        }
-        "a" => {
-          let current_dx = movement.borrow().dx;
-          movement.borrow_mut().dx = next_delta(current_dx, 1);
-        }
-        "d" => {
-          let current_dx = movement.borrow().dx;
-          movement.borrow_mut().dx = next_delta(current_dx, -1);
-        }
-        _ => {}
-      }
+    let click = click.clone();
+    let viewport = viewport.clone();
+    add_event_and_forget(renderer.borrow().canvas(), "mousedown", move |e| {
+      let me = e.dyn_into::<MouseEvent>().unwrap();
+      let locked = gloo_window()
+        .document()
+        .and_then(|document| document.pointer_lock_element())
+        .is_some();
+      // With the pointer locked, the cursor is always at the center
+      let position = if locked {
+        viewport.borrow().center()
+      } else {
+        vector![me.offset_x() as f32, me.offset_y() as f32]
+      };
+      click.set(Some(position));
     });
   }
+  // Actions rebound in an earlier session win over the defaults, the rest keep their keys
+  let bindings = Bindings::default()
+    .bind_axis("move_forward", "KeyS", "KeyW")
+    .bind_axis("move_forward", "ArrowDown", "ArrowUp")
+    .bind_axis("move_right", "KeyA", "KeyD")
+    .bind_axis("move_right", "ArrowLeft", "ArrowRight")
+    .bind("jump", "Space")
+    .bind("toggle_stats", "Backquote")
+    .bind("toggle_physics", "F2")
+    .bind("toggle_wireframe", "F4")
+    .bind("intro", "KeyI")
+    .bind("minimap", "KeyM")
+    .bind("camera", "KeyC")
+    .bind("record", "F9")
+    .merge(Bindings::load(BINDINGS_KEY).unwrap_or_default());
+  let input = Rc::new(RefCell::new(Input::new(bindings)));
+  Input::listen(input.clone());
 
+  let mut character = Character::new("sphere", CharacterConfig::default());
+  let mut camera_mode = 0;
+  let mut jump = false;
   let mut first_frame = true;
+  let mut last_frame = None;
+  let mut failed = false;
 
   on_animation_frame(
     move |_| {
+      if renderer.borrow_mut().poll_device() {
+        // Before the meshes, so the monitor binds the new texture
+        monitor.restore(&renderer.borrow());
+        scene.restore(&renderer.borrow());
+      }
+      // Nothing renders without a device, so say why instead of leaving the last frame up
+      if !failed && renderer.borrow().has_failed() {
+        failed = true;
+        if let Err(err) = show_unavailable() {
+          log!("Couldn't show the error", err);
+        }
+      }
+      {
+        let input = input.borrow();
+        if input.pressed("toggle_stats") {
+          stats.borrow().toggle();
+        }
+        if input.pressed("toggle_physics") {
+          debug_mode.borrow_mut().toggle_physics();
+        }
+        if input.pressed("toggle_wireframe") {
+          debug_mode.borrow_mut().toggle_wireframe();
+        }
+        if input.pressed("intro") {
+          viewport.borrow_mut().play(intro(), 1.5);
+        }
+        if input.pressed("minimap") {
+          show_minimap.set(!show_minimap.get());
+        }
+        if input.pressed("camera") {
+          camera_mode = (camera_mode + 1) % 4;
+          let mut viewport = viewport.borrow_mut();
+          match camera_mode {
+            0 => viewport.set_controller(Orbit::new(10.)),
+            1 => viewport.set_controller(Chase::new(8., 2.)),
+            2 => viewport.set_controller(FirstPerson::new(0.5)),
+            _ => {
+              let eye = viewport.view().inverse().translation.vector;
+              viewport.set_controller(FreeFly::new(eye.into()));
+            }
+          }
+        }
+        if input.pressed("record") && !renderer.borrow().is_capturing() {
+          // Waiting frames and the archive take about this much each, so big canvases get
+          // shorter recordings instead of running out of memory
+          let frames = {
+            let canvas = renderer.borrow().canvas().clone();
+            let frame_bytes = canvas.width() as u64 * canvas.height() as u64 * 4;
+            (RECORD_BYTES / frame_bytes.max(1)).clamp(1, RECORD_FRAMES as u64) as u32
+          };
+          let frames = renderer.borrow_mut().capture_sequence(frames);
+          wasm_bindgen_futures::spawn_local(async move {
+            let saved = async {
+              let mut zip = ZipWriter::new();
+              for (i, frame) in frames.into_iter().enumerate() {
+                let png = frame.await?.to_png();
+                zip.add(&format!("frame-{:04}.png", i), &png)?;
+              }
+              capture::download_blob(&zip.finish()?, "frames.zip")
+            };
+            if let Err(err) = saved.await {
+              log!("Couldn't record frames", err);
+            }
+          });
+        }
+        // Kept until a physics step can act on it, which doesn't happen while paused
+        if !game.paused() {
+          jump |= input.pressed("jump");
+        }
+      }
+      // Stays 0 while paused
+      let mut frame_dt = 0.;
       if !game.paused() || first_frame {
-        scene.physics();
-        let Movement { dx, dy } = *movement.borrow();
-        let body = scene.get_body_mut("sphere").unwrap();
-        if dx != 0 || dy != 0 {
-          body.apply_impulse(vector![dx as f32, 0., -dy as f32], true);
+        let now = stats::now();
+        // Without a previous frame, e.g. the first one or after a pause, run one step
+        let dt = last_frame
+          .replace(now)
+          .map_or(scene.timestep(), |last| ((now - last) / 1000.) as f32);
+        frame_dt = dt;
+        let (forward, right) = {
+          let input = input.borrow();
+          (input.axis("move_forward"), input.axis("move_right"))
+        };
+        // A free flying camera takes the movement keys for itself
+        let flying = viewport.borrow().captures_movement();
+        if flying {
+          viewport
+            .borrow_mut()
+            .update_movement(vector![right, 0., forward]);
+        }
+        let camera = viewport.borrow().view().rotation.inverse();
+        let (forward, right) = if flying { (0., 0.) } else { (forward, right) };
+        let physics = stats::time(|| {
+          // The character moves with every fixed step, so it stays in lockstep with the bodies
+          let stepped = scene.update(dt, |scene, timestep| {
+            if std::mem::take(&mut jump) {
+              character.jump();
+            }
+            character.update(scene, forward, right, &camera, timestep);
+          });
+          if let Err(err) = stepped {
+            log!("Couldn't keep history", err.to_string());
+          }
+        });
+        stats.borrow_mut().record_physics(physics);
+        let sphere = scene.get_isometry("sphere").unwrap();
+        viewport.borrow_mut().follow(sphere);
+        viewport.borrow_mut().update(dt);
+        minimap.borrow_mut().follow(sphere);
+        minimap.borrow_mut().update(dt);
+        security_camera.follow(sphere);
+        security_camera.update(dt);
+        let filter = scene.filter_excluding("sphere");
+        viewport
+          .borrow_mut()
+          .avoid_obstacles(|origin, dir, max_toi| {
+            scene
+              .raycast(origin, dir, max_toi, filter)
+              .map(|hit| hit.toi)
+          });
+        if let Some(position) = click.take() {
+          // The minimap is drawn over the main view, so it gets the clicks inside it
+          let minimap = minimap.borrow();
+          let viewport = viewport.borrow();
+          let under_cursor = if show_minimap.get() && minimap.contains(position.x, position.y) {
+            &minimap
+          } else {
+            &viewport
+          };
+          let ray = under_cursor.screen_to_ray(position.x, position.y);
+          let hit = ray
+            .and_then(|(origin, dir)| scene.raycast(origin, dir, f32::MAX, QueryFilter::default()));
+          scene.select(hit.as_ref().map(|hit| hit.entity.as_str()));
         }
-        viewport.borrow_mut().follow(*body.position());
-        renderer
+        if debug_mode.borrow().physics() {
+          let mut debug = debug.borrow_mut();
+          debug.axes(&sphere, 1.5);
+          let label = sphere.translation.vector + vector![0., 1.5, 0.];
+          debug.text(label.into(), "sphere", 0.3, Color::rgb(1., 1., 1.));
+        }
+      } else {
+        last_frame = None;
+      }
+      // Screenshots taken from the pause menu still need a frame to read back
+      if !game.paused() || first_frame || renderer.borrow().is_capturing() {
+        *scene.debug_mode_mut() = *debug_mode.borrow();
+        scene.debug_render(&mut debug_lines);
+        debug
           .borrow_mut()
-          .render(scene.meshes(), &scene.simiarities(), &viewport.borrow());
+          .flush(&mut debug_lines, &viewport.borrow(), frame_dt);
+        let render = stats::time(|| {
+          let (viewport, minimap) = (viewport.borrow(), minimap.borrow());
+          let viewports: &[&Viewport] = if show_minimap.get() {
+            &[&viewport, &minimap]
+          } else {
+            &[&viewport]
+          };
+          let models = scene.models();
+          let mut renderer = renderer.borrow_mut();
+          renderer.render_to(&monitor, scene.meshes(), &models, &security_camera);
+          renderer.render(scene.meshes(), &models, &debug_lines, viewports)
+        });
+        let renderer = renderer.borrow();
+        let mut stats = stats.borrow_mut();
+        stats.record_render(render, renderer.frame_stats(), renderer.gpu_time());
+        stats.end_frame();
       }
+      input.borrow_mut().end_frame();
       if first_frame {
         first_frame = false;
       }
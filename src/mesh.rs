@@ -1,4 +1,5 @@
 use crate::renderer::Rect;
+use crate::target::RenderTarget;
 use crate::Color;
 use crate::{iter_to_array, renderer::Renderer};
 use genmesh::{
@@ -6,11 +7,12 @@ use genmesh::{
   EmitTriangles, Triangulate, Vertex,
 };
 use js_sys::Object;
+use nalgebra::Vector3;
 use wasm_bindgen::JsValue;
 use web_sys::{
   gpu_buffer_usage, GpuBindGroup, GpuBindGroupDescriptor, GpuBindGroupEntry, GpuBuffer,
   GpuBufferBinding, GpuBufferDescriptor, GpuImageCopyExternalImage, GpuImageCopyTextureTagged,
-  GpuTextureViewDescriptor, GpuTextureViewDimension,
+  GpuTextureViewDescriptor, GpuTextureViewDimension, ImageBitmap,
 };
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -21,11 +23,14 @@ pub enum MaterialType {
   CubeMap = 3,
 }
 
+#[derive(Clone)]
 pub struct Material {
   pub material_type: MaterialType,
   pub vertex_colors: Vec<[f32; 3]>,
   pub texture_coordinates: Vec<[f32; 2]>,
   pub texture_src: Vec<String>,
+  // Samples a render target instead of loading `texture_src`
+  pub target: Option<RenderTarget>,
   pub color: Color,
 }
 
@@ -36,6 +41,7 @@ impl Material {
       vertex_colors: vec![],
       texture_coordinates: vec![],
       texture_src: vec![],
+      target: None,
       color,
     }
   }
@@ -45,6 +51,7 @@ impl Material {
       vertex_colors: colors,
       texture_coordinates: vec![],
       texture_src: vec![],
+      target: None,
       color: Color {
         r: 1.,
         g: 1.,
@@ -59,6 +66,22 @@ impl Material {
       vertex_colors: vec![],
       texture_coordinates: coordinates,
       texture_src: vec![src.to_string()],
+      target: None,
+      color: Color {
+        r: 0.1,
+        g: 0.1,
+        b: 0.1,
+        a: 1.,
+      },
+    }
+  }
+  pub fn render_target(target: &RenderTarget, coordinates: Vec<[f32; 2]>) -> Self {
+    Self {
+      material_type: MaterialType::Textured,
+      vertex_colors: vec![],
+      texture_coordinates: coordinates,
+      texture_src: vec![],
+      target: Some(target.clone()),
       color: Color {
         r: 0.1,
         g: 0.1,
@@ -73,6 +96,7 @@ impl Material {
       vertex_colors: vec![],
       texture_coordinates: vec![],
       texture_src: src_set.iter().map(|s| s.to_string()).collect(),
+      target: None,
       color: Color {
         r: 0.,
         g: 0.,
@@ -83,6 +107,7 @@ impl Material {
   }
 }
 
+#[derive(Clone)]
 pub struct Geometry {
   pub vertices: Vec<[f32; 3]>,
   pub indices: Vec<u16>,
@@ -105,6 +130,21 @@ impl Geometry {
       .collect();
     Geometry { vertices, indices }
   }
+  // Area weighted vertex normals
+  pub fn normals(&self) -> Vec<Vector3<f32>> {
+    let mut normals = vec![Vector3::zeros(); self.vertices.len()];
+    for tri in self.indices.chunks(3) {
+      let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vector3::from(self.vertices[i as usize]));
+      let normal = (b - a).cross(&(c - a));
+      for i in tri {
+        normals[*i as usize] += normal;
+      }
+    }
+    normals
+      .into_iter()
+      .map(|n| n.try_normalize(f32::EPSILON).unwrap_or_default())
+      .collect()
+  }
 }
 
 pub struct Mesh {
@@ -112,6 +152,8 @@ pub struct Mesh {
   pub index_count: u32,
   pub material_type: MaterialType,
   pub color: Color,
+  // Tints the whole mesh, the alpha is how much
+  pub highlight: Option<Color>,
 
   pub vertex_buffer: GpuBuffer,
   pub index_buffer: GpuBuffer,
@@ -122,6 +164,10 @@ pub struct Mesh {
 
   pub texture_coordinates: GpuBuffer,
   pub texture_bind_group: GpuBindGroup,
+
+  geometry: Geometry,
+  material: Material,
+  bitmaps: Vec<ImageBitmap>,
 }
 
 impl Mesh {
@@ -130,12 +176,35 @@ impl Mesh {
     geometry: &Geometry,
     material: &Material,
   ) -> Result<Self, JsValue> {
+    let mut bitmaps = vec![];
+    for each in material.texture_src.iter() {
+      let (bitmap, _) = Renderer::create_bitmap(each).await?;
+      bitmaps.push(bitmap);
+    }
+    Ok(Self::create(renderer, geometry, material, &bitmaps))
+  }
+  pub fn geometry(&self) -> &Geometry {
+    &self.geometry
+  }
+  pub fn samples(&self, target: &RenderTarget) -> bool {
+    self
+      .material
+      .target
+      .as_ref()
+      .is_some_and(|own| own.same(target))
+  }
+  pub fn restore(&mut self, renderer: &Renderer) {
+    let highlight = self.highlight;
+    *self = Self::create(renderer, &self.geometry, &self.material, &self.bitmaps);
+    self.highlight = highlight;
+  }
+  fn create(
+    renderer: &Renderer,
+    geometry: &Geometry,
+    material: &Material,
+    bitmaps: &[ImageBitmap],
+  ) -> Self {
     let device = renderer.device();
-    let pipeline = if material.material_type == MaterialType::CubeMap {
-      renderer.pipeline_cubebox()
-    } else {
-      renderer.pipeline()
-    };
     let vertex_buffer = {
       let vertices: Vec<f32> = geometry.vertices.iter().flatten().copied().collect();
       renderer.create_buffer(&vertices)
@@ -160,28 +229,27 @@ impl Mesh {
     };
 
     let texture_bind_group = {
-      let mut bitmaps = vec![];
-      let mut rect = None;
-      for each in material.texture_src.iter() {
-        let (texture, r) = Renderer::create_bitmap(each).await?;
-        if rect.is_none() {
-          rect = Some(r);
-        }
-        bitmaps.push(texture);
-      }
-      let rect = rect.unwrap_or(Rect {
-        width: 1,
-        height: 1,
-      });
-      let texture = if material.material_type == MaterialType::CubeMap {
+      let rect = bitmaps
+        .first()
+        .map(|bitmap| Rect {
+          width: bitmap.width(),
+          height: bitmap.height(),
+        })
+        .unwrap_or(Rect {
+          width: 1,
+          height: 1,
+        });
+      let texture = if let Some(target) = &material.target {
+        target.color()
+      } else if material.material_type == MaterialType::CubeMap {
         renderer.create_texture(&rect, 6)
       } else {
         renderer.create_texture(&rect, 1)
       };
-      for (i, bitmap) in bitmaps.into_iter().enumerate() {
+      for (i, bitmap) in bitmaps.iter().enumerate() {
         let mut source = GpuImageCopyExternalImage::new(&Object::new());
         source.flip_y(false);
-        source.source(&Object::from(bitmap));
+        source.source(bitmap);
         let dest = if material.material_type == MaterialType::CubeMap {
           let mut dest = GpuImageCopyTextureTagged::new(&texture);
           dest.origin(&iter_to_array([0, 0, i as i32]));
@@ -219,13 +287,13 @@ impl Mesh {
           .device()
           .create_bind_group(&GpuBindGroupDescriptor::new(
             &iter_to_array(&entries),
-            &pipeline.get_bind_group_layout(1),
+            renderer.texture_layout(material.material_type),
           ));
       texture_binding_group
     };
 
     let uniform_buffer = device.create_buffer(&GpuBufferDescriptor::new(
-      96.,
+      112.,
       gpu_buffer_usage::UNIFORM | gpu_buffer_usage::COPY_DST,
     ));
 
@@ -236,14 +304,15 @@ impl Mesh {
           0,
           &GpuBufferBinding::new(&uniform_buffer),
         ))]),
-        &pipeline.get_bind_group_layout(0),
+        renderer.uniform_layout(),
       ));
 
-    Ok(Self {
+    Self {
       vertext_count: geometry.vertices.len() as u32,
       index_count: geometry.indices.len() as u32,
       material_type: material.material_type,
       color: material.color,
+      highlight: None,
       vertex_buffer,
       index_buffer,
       vertex_colors,
@@ -251,6 +320,38 @@ impl Mesh {
       uniform_bind_group,
       texture_coordinates,
       texture_bind_group,
-    })
+      geometry: geometry.clone(),
+      material: material.clone(),
+      bitmaps: bitmaps.to_vec(),
+    }
+  }
+}
+
+#[cfg(test)]
+impl Mesh {
+  // Stands in for a mesh in native tests of the simulation; its GPU objects are `undefined`
+  // and must never be used
+  pub fn placeholder(geometry: Geometry) -> Self {
+    use wasm_bindgen::JsCast;
+    let buffer = || JsValue::UNDEFINED.unchecked_into::<GpuBuffer>();
+    let bind_group = || JsValue::UNDEFINED.unchecked_into::<GpuBindGroup>();
+    let material = Material::new(Color::rgb(1., 1., 1.));
+    Self {
+      vertext_count: geometry.vertices.len() as u32,
+      index_count: geometry.indices.len() as u32,
+      material_type: material.material_type,
+      color: material.color,
+      highlight: None,
+      vertex_buffer: buffer(),
+      index_buffer: buffer(),
+      vertex_colors: buffer(),
+      uniform_buffer: buffer(),
+      uniform_bind_group: bind_group(),
+      texture_coordinates: buffer(),
+      texture_bind_group: bind_group(),
+      geometry,
+      material,
+      bitmaps: Vec::new(),
+    }
   }
 }
@@ -1,5 +0,0 @@
-#[derive(Debug)]
-pub struct Movement {
-  pub dx: isize,
-  pub dy: isize,
-}
@@ -0,0 +1,158 @@
+use nalgebra::{Isometry3, Point3, SVector, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+  // Passes through every keyframe
+  CatmullRom,
+  // Passes through the first and last keyframes, the rest pull the curve towards them
+  Bezier,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Easing {
+  #[default]
+  Linear,
+  EaseIn,
+  EaseOut,
+  EaseInOut,
+}
+
+impl Easing {
+  // Maps 0..=1 onto 0..=1
+  pub fn apply(&self, t: f32) -> f32 {
+    let t = t.clamp(0., 1.);
+    match self {
+      Self::Linear => t,
+      Self::EaseIn => t * t * t,
+      Self::EaseOut => 1. - (1. - t).powi(3),
+      Self::EaseInOut => t * t * (3. - 2. * t),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+  // Seconds from the start of the path
+  pub time: f32,
+  pub position: Point3<f32>,
+  pub look_at: Point3<f32>,
+  // Vertical field of view in radians
+  pub fov: f32,
+}
+
+// Position, look at and fov packed together so they interpolate as one
+type State = SVector<f32, 7>;
+
+impl Keyframe {
+  fn state(&self) -> State {
+    let (p, l) = (self.position, self.look_at);
+    State::from([p.x, p.y, p.z, l.x, l.y, l.z, self.fov])
+  }
+}
+
+fn catmull_rom(p0: &State, p1: &State, p2: &State, p3: &State, u: f32) -> State {
+  let (u2, u3) = (u * u, u * u * u);
+  (p1 * 2.
+    + (p2 - p0) * u
+    + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * u2
+    + (p1 * 3. - p0 - p2 * 3. + p3) * u3)
+    * 0.5
+}
+
+fn bezier(points: &[State], u: f32) -> State {
+  let mut points = points.to_vec();
+  for n in (1..points.len()).rev() {
+    for i in 0..n {
+      points[i] = points[i].lerp(&points[i + 1], u);
+    }
+  }
+  points[0]
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+  keyframes: Vec<Keyframe>,
+  pub interpolation: Interpolation,
+  pub easing: Easing,
+  pub up: Vector3<f32>,
+}
+
+impl CameraPath {
+  pub fn new(interpolation: Interpolation) -> Self {
+    Self {
+      keyframes: Vec::new(),
+      interpolation,
+      easing: Easing::default(),
+      up: Vector3::y(),
+    }
+  }
+  pub fn keyframe(
+    mut self,
+    time: f32,
+    position: Point3<f32>,
+    look_at: Point3<f32>,
+    fov: f32,
+  ) -> Self {
+    let index = self.keyframes.partition_point(|k| k.time <= time);
+    self.keyframes.insert(
+      index,
+      Keyframe {
+        time,
+        position,
+        look_at,
+        fov,
+      },
+    );
+    self
+  }
+  pub fn easing(mut self, easing: Easing) -> Self {
+    self.easing = easing;
+    self
+  }
+  pub fn up(mut self, up: Vector3<f32>) -> Self {
+    self.up = up;
+    self
+  }
+  pub fn keyframes(&self) -> &[Keyframe] {
+    &self.keyframes
+  }
+  pub fn duration(&self) -> f32 {
+    self.keyframes.last().map_or(0., |k| k.time)
+  }
+  fn state_at(&self, time: f32) -> Option<State> {
+    let (first, last) = (self.keyframes.first()?, self.keyframes.last()?);
+    let span = last.time - first.time;
+    if span <= 0. {
+      return Some(first.state());
+    }
+    let u = self.easing.apply((time - first.time) / span);
+    let time = first.time + u * span;
+    let states: Vec<State> = self.keyframes.iter().map(Keyframe::state).collect();
+    Some(match self.interpolation {
+      Interpolation::Bezier => bezier(&states, u),
+      Interpolation::CatmullRom => {
+        let i = self
+          .keyframes
+          .partition_point(|k| k.time <= time)
+          .clamp(1, states.len() - 1)
+          - 1;
+        let (start, end) = (self.keyframes[i].time, self.keyframes[i + 1].time);
+        let u = ((time - start) / (end - start).max(f32::EPSILON)).clamp(0., 1.);
+        // The ends repeat so the curve still reaches the first and last keyframes
+        let p0 = &states[i.saturating_sub(1)];
+        let p3 = &states[(i + 2).min(states.len() - 1)];
+        catmull_rom(p0, &states[i], &states[i + 1], p3, u)
+      }
+    })
+  }
+  // The view and fov at `time`, clamped to the ends of the path
+  pub fn sample(&self, time: f32) -> Option<(Isometry3<f32>, f32)> {
+    let state = self.state_at(time)?;
+    let position = Point3::new(state[0], state[1], state[2]);
+    let look_at = Point3::new(state[3], state[4], state[5]);
+    Some((
+      Isometry3::look_at_rh(&position, &look_at, &self.up),
+      state[6],
+    ))
+  }
+}
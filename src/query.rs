@@ -0,0 +1,26 @@
+use nalgebra::{Point3, Vector3};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RayHit {
+  pub entity: String,
+  pub toi: f32,
+  pub point: Point3<f32>,
+  pub normal: Vector3<f32>,
+}
+
+// `point` and `normal` are on the entity that was hit, in world space
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapeHit {
+  pub entity: String,
+  pub toi: f32,
+  pub point: Point3<f32>,
+  pub normal: Vector3<f32>,
+  pub penetrating: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointHit {
+  pub entity: String,
+  pub point: Point3<f32>,
+  pub inside: bool,
+}
@@ -1,39 +1,165 @@
+use crate::capture::{Capture, Image};
+use crate::debug::{DebugLines, LineRenderer};
 use crate::iter_to_array;
 use crate::mesh::MaterialType;
 use crate::mesh::Mesh;
+use crate::stats::{GpuTimer, RenderStats};
+use crate::target::RenderTarget;
 use crate::viewport::Viewport;
+use gloo_console::log;
 use gloo_utils::format::JsValueSerdeExt;
 use gloo_utils::window;
 use js_sys::Float32Array;
+use js_sys::Reflect;
 use js_sys::Uint16Array;
-use nalgebra::Similarity3;
+use js_sys::Uint8Array;
+use nalgebra::Matrix4;
 use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::rc::Rc;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::JsValue;
-use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
 use web_sys::{
-  gpu_buffer_usage, gpu_texture_usage, Blob, GpuAdapter, GpuAddressMode, GpuBuffer,
-  GpuBufferDescriptor, GpuCanvasAlphaMode, GpuCanvasConfiguration, GpuCanvasContext,
-  GpuColorTargetState, GpuCompareFunction, GpuCullMode, GpuDepthStencilState, GpuDevice,
-  GpuFilterMode, GpuFragmentState, GpuFrontFace, GpuIndexFormat, GpuLoadOp, GpuPrimitiveState,
-  GpuPrimitiveTopology, GpuRenderPassColorAttachment, GpuRenderPassDepthStencilAttachment,
-  GpuRenderPassDescriptor, GpuRenderPipeline, GpuRenderPipelineDescriptor, GpuSampler,
-  GpuSamplerDescriptor, GpuShaderModuleDescriptor, GpuStoreOp, GpuTexture, GpuTextureDescriptor,
-  GpuTextureDimension, GpuTextureFormat, GpuVertexAttribute, GpuVertexBufferLayout,
-  GpuVertexFormat, GpuVertexState, HtmlCanvasElement, ImageBitmap, Response,
+  gpu_buffer_usage, gpu_map_mode, gpu_shader_stage, gpu_texture_usage, Blob, Gpu, GpuAdapter,
+  GpuAddressMode, GpuBindGroupLayout, GpuBindGroupLayoutDescriptor, GpuBindGroupLayoutEntry,
+  GpuBuffer, GpuBufferBindingLayout, GpuBufferDescriptor, GpuCanvasAlphaMode,
+  GpuCanvasConfiguration, GpuCanvasContext, GpuColorTargetState, GpuCompareFunction, GpuCullMode,
+  GpuDepthStencilState, GpuDevice, GpuDeviceDescriptor, GpuFilterMode, GpuFragmentState,
+  GpuFrontFace, GpuImageCopyBuffer, GpuImageCopyTexture, GpuIndexFormat, GpuLoadOp,
+  GpuPipelineLayoutDescriptor, GpuPrimitiveState, GpuPrimitiveTopology,
+  GpuRenderPassColorAttachment, GpuRenderPassDepthStencilAttachment, GpuRenderPassDescriptor,
+  GpuRenderPipeline, GpuRenderPipelineDescriptor, GpuSampler, GpuSamplerBindingLayout,
+  GpuSamplerDescriptor, GpuShaderModuleDescriptor, GpuStoreOp, GpuTexture, GpuTextureBindingLayout,
+  GpuTextureDescriptor, GpuTextureDimension, GpuTextureFormat, GpuTextureViewDimension,
+  GpuVertexAttribute, GpuVertexBufferLayout, GpuVertexFormat, GpuVertexState, HtmlCanvasElement,
+  ImageBitmap, Response,
 };
 
+// Reverse-Z keeps float precision where the distances are large, which a planet needs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DepthMode {
+  Standard,
+  #[default]
+  ReverseZ,
+}
+
+impl DepthMode {
+  pub fn format(&self) -> GpuTextureFormat {
+    match self {
+      Self::Standard => GpuTextureFormat::Depth24plusStencil8,
+      Self::ReverseZ => GpuTextureFormat::Depth32float,
+    }
+  }
+  fn clear_value(&self) -> f32 {
+    match self {
+      Self::Standard => 1.,
+      Self::ReverseZ => 0.,
+    }
+  }
+  pub(crate) fn attachment(&self, texture: &GpuTexture) -> GpuRenderPassDepthStencilAttachment {
+    let mut attachment = GpuRenderPassDepthStencilAttachment::new(&texture.create_view());
+    attachment
+      .depth_clear_value(self.clear_value())
+      .depth_load_op(GpuLoadOp::Clear)
+      .depth_store_op(GpuStoreOp::Store);
+    // Stencil ops are only valid when the format has a stencil aspect
+    if *self == Self::Standard {
+      attachment
+        .stencil_clear_value(0)
+        .stencil_load_op(GpuLoadOp::Clear)
+        .stencil_store_op(GpuStoreOp::Store);
+    }
+    attachment
+  }
+  fn compare(&self) -> GpuCompareFunction {
+    match self {
+      Self::Standard => GpuCompareFunction::Less,
+      Self::ReverseZ => GpuCompareFunction::GreaterEqual,
+    }
+  }
+}
+
+// Where a viewport draws to and what else goes into its pass
+struct Pass<'a> {
+  color: GpuRenderPassColorAttachment,
+  depth: GpuRenderPassDepthStencilAttachment,
+  width: u32,
+  height: u32,
+  lines: Option<&'a DebugLines>,
+  // Whether this is the frame's first and last pass, for the GPU timer
+  timestamps: Option<(bool, bool)>,
+  // Meshes sampling the target can't be drawn into it
+  target: Option<&'a RenderTarget>,
+}
+
+enum DeviceStatus {
+  Ready,
+  Lost,
+  Requesting,
+  Recovered(GpuDevice),
+  Failed,
+}
+
+// Bind group layouts shared by the pipelines instead of "auto" ones, which are tied to the
+// pipeline they came from; so mesh bind groups stay valid when the pipelines are rebuilt
+struct Layouts {
+  uniforms: GpuBindGroupLayout,
+  texture: GpuBindGroupLayout,
+  cube_texture: GpuBindGroupLayout,
+}
+
+impl Layouts {
+  fn new(device: &GpuDevice) -> Self {
+    let visibility = gpu_shader_stage::VERTEX | gpu_shader_stage::FRAGMENT;
+    let mut uniforms = GpuBindGroupLayoutEntry::new(0, visibility);
+    uniforms.buffer(&GpuBufferBindingLayout::new());
+    let texture = |dimension| {
+      let mut sampler = GpuBindGroupLayoutEntry::new(0, gpu_shader_stage::FRAGMENT);
+      sampler.sampler(&GpuSamplerBindingLayout::new());
+      let mut texture = GpuBindGroupLayoutEntry::new(1, gpu_shader_stage::FRAGMENT);
+      texture.texture(GpuTextureBindingLayout::new().view_dimension(dimension));
+      device.create_bind_group_layout(&GpuBindGroupLayoutDescriptor::new(&iter_to_array(&[
+        sampler, texture,
+      ])))
+    };
+    Self {
+      uniforms: device.create_bind_group_layout(&GpuBindGroupLayoutDescriptor::new(
+        &iter_to_array(&[uniforms]),
+      )),
+      texture: texture(GpuTextureViewDimension::N2d),
+      cube_texture: texture(GpuTextureViewDimension::Cube),
+    }
+  }
+}
+
+// The mesh pipelines for one depth mode
+struct Pipelines {
+  mesh: GpuRenderPipeline,
+  cubemap: GpuRenderPipeline,
+}
+
 pub struct Renderer {
   canvas: HtmlCanvasElement,
   context: GpuCanvasContext,
+  gpu: Gpu,
+  format: GpuTextureFormat,
   device: GpuDevice,
-  pipeline: GpuRenderPipeline,
-  pipeline_cubebox: GpuRenderPipeline,
-  depth_texture: GpuTexture,
+  status: Rc<RefCell<DeviceStatus>>,
+  layouts: Layouts,
+  // Both depth modes stay ready, each pass picks the one its projection needs
+  standard: Pipelines,
+  reverse_z: Pipelines,
   color_attachment: GpuRenderPassColorAttachment,
-  depth_attachment: GpuRenderPassDepthStencilAttachment,
-  render_pass_descriptor: GpuRenderPassDescriptor,
+  // The canvas depth buffer of each mode in use, created on first use
+  depth_attachments: Vec<(DepthMode, GpuRenderPassDepthStencilAttachment)>,
   sampler: GpuSampler,
+  captures: Vec<Capture>,
+  timer: Option<GpuTimer>,
+  lines: LineRenderer,
+  counts: Cell<RenderStats>,
+  frame_stats: RenderStats,
 }
 
 impl Renderer {
@@ -41,9 +167,10 @@ impl Renderer {
     device: &GpuDevice,
     width: u32,
     height: u32,
-  ) -> (GpuTexture, GpuRenderPassDepthStencilAttachment) {
+    depth_mode: DepthMode,
+  ) -> GpuRenderPassDepthStencilAttachment {
     let depth_descriptor = GpuTextureDescriptor::new(
-      GpuTextureFormat::Depth24plusStencil8,
+      depth_mode.format(),
       &iter_to_array(&[
         JsValue::from_f64(width as f64),
         JsValue::from_f64(height as f64),
@@ -52,40 +179,155 @@ impl Renderer {
     );
 
     let depth_texture = device.create_texture(&depth_descriptor);
-    let mut depth_attachment =
-      GpuRenderPassDepthStencilAttachment::new(&depth_texture.create_view());
-    depth_attachment
-      .depth_clear_value(1.)
-      .depth_load_op(GpuLoadOp::Clear)
-      .depth_store_op(GpuStoreOp::Store)
-      .stencil_clear_value(0)
-      .stencil_load_op(GpuLoadOp::Clear)
-      .stencil_store_op(GpuStoreOp::Store);
-    (depth_texture, depth_attachment)
+    depth_mode.attachment(&depth_texture)
+  }
+  async fn request_device(gpu: &Gpu) -> Result<GpuDevice, JsValue> {
+    let adapter = JsFuture::from(gpu.request_adapter()).await?;
+    if adapter.is_null() {
+      return Err(JsValue::from_str("No suitable GPU adapter found"));
+    }
+    let adapter = adapter.dyn_into::<GpuAdapter>()?;
+    let mut descriptor = GpuDeviceDescriptor::new();
+    if adapter.features().has("timestamp-query") {
+      descriptor.required_features(&iter_to_array(["timestamp-query"]));
+    }
+    let device = JsFuture::from(adapter.request_device_with_descriptor(&descriptor))
+      .await?
+      .dyn_into::<GpuDevice>()?;
+    Ok(device)
+  }
+  fn watch_device(device: &GpuDevice, status: Rc<RefCell<DeviceStatus>>) {
+    let lost = device.lost();
+    spawn_local(async move {
+      if let Ok(info) = JsFuture::from(lost).await {
+        log!("GPU device lost", info);
+        *status.borrow_mut() = DeviceStatus::Lost;
+      }
+    });
+  }
+  fn create_pipelines(
+    device: &GpuDevice,
+    layouts: &Layouts,
+    format: GpuTextureFormat,
+    depth_mode: DepthMode,
+  ) -> Pipelines {
+    let layout = |texture: &GpuBindGroupLayout| {
+      device.create_pipeline_layout(&GpuPipelineLayoutDescriptor::new(&iter_to_array(&[
+        JsValue::from(&layouts.uniforms),
+        JsValue::from(texture),
+      ])))
+    };
+    let shader =
+      device.create_shader_module(&GpuShaderModuleDescriptor::new(include_str!("shader.wgsl")));
+    let position_attribute_description = GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 0);
+    let vertex_color_attribute_description =
+      GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 1);
+    let tex_coords_attribute_description =
+      GpuVertexAttribute::new(GpuVertexFormat::Float32x2, 0., 2);
+    let mut vertex_state = GpuVertexState::new(&shader);
+    vertex_state.entry_point("vs_main");
+    vertex_state.buffers(&iter_to_array(&[
+      GpuVertexBufferLayout::new(4. * 3., &iter_to_array(&[position_attribute_description])),
+      GpuVertexBufferLayout::new(
+        4. * 3.,
+        &iter_to_array(&[vertex_color_attribute_description]),
+      ),
+      GpuVertexBufferLayout::new(4. * 2., &iter_to_array(&[tex_coords_attribute_description])),
+    ]));
+    let mut fragment_state =
+      GpuFragmentState::new(&shader, &iter_to_array(&[GpuColorTargetState::new(format)]));
+    fragment_state.entry_point("fs_main");
+    let pipeline = device.create_render_pipeline(
+      GpuRenderPipelineDescriptor::new(&layout(&layouts.texture), &vertex_state)
+        .label("Defualt Render pipeline")
+        .fragment(&fragment_state)
+        .primitive(
+          GpuPrimitiveState::new()
+            .front_face(GpuFrontFace::Ccw)
+            .cull_mode(GpuCullMode::Back)
+            .topology(GpuPrimitiveTopology::TriangleList),
+        )
+        .depth_stencil(
+          GpuDepthStencilState::new(depth_mode.format())
+            .depth_compare(depth_mode.compare())
+            .depth_write_enabled(true),
+        ),
+    );
+    let cubemap_shader = device.create_shader_module(&GpuShaderModuleDescriptor::new(
+      include_str!("shader_cube.wgsl"),
+    ));
+    let position_attribute_description = GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 0);
+    let tex_coords_attribute_description =
+      GpuVertexAttribute::new(GpuVertexFormat::Float32x2, 0., 1);
+    let mut vertex_state = GpuVertexState::new(&cubemap_shader);
+    vertex_state.entry_point("vs_main");
+    vertex_state.buffers(&iter_to_array(&[
+      GpuVertexBufferLayout::new(4. * 3., &iter_to_array(&[position_attribute_description])),
+      GpuVertexBufferLayout::new(4. * 2., &iter_to_array(&[tex_coords_attribute_description])),
+    ]));
+    let mut fragment_state = GpuFragmentState::new(
+      &cubemap_shader,
+      &iter_to_array(&[GpuColorTargetState::new(format)]),
+    );
+    fragment_state.entry_point("fs_main");
+    let pipeline_cubemap = device.create_render_pipeline(
+      GpuRenderPipelineDescriptor::new(&layout(&layouts.cube_texture), &vertex_state)
+        .label("Cubemap Render pipeline")
+        .fragment(&fragment_state)
+        .primitive(
+          GpuPrimitiveState::new()
+            .front_face(GpuFrontFace::Ccw)
+            .cull_mode(GpuCullMode::Front)
+            .topology(GpuPrimitiveTopology::TriangleList),
+        )
+        .depth_stencil(
+          GpuDepthStencilState::new(depth_mode.format())
+            .depth_compare(depth_mode.compare())
+            .depth_write_enabled(true),
+        ),
+    );
+    Pipelines {
+      mesh: pipeline,
+      cubemap: pipeline_cubemap,
+    }
+  }
+  fn create_sampler(device: &GpuDevice) -> GpuSampler {
+    let mut sampler_desc = GpuSamplerDescriptor::new();
+    sampler_desc.address_mode_u(GpuAddressMode::Repeat);
+    sampler_desc.address_mode_v(GpuAddressMode::Repeat);
+    sampler_desc.mag_filter(GpuFilterMode::Linear);
+    device.create_sampler_with_descriptor(&sampler_desc)
+  }
+  fn configure(context: &GpuCanvasContext, device: &GpuDevice, format: GpuTextureFormat) {
+    let mut ctx_config = GpuCanvasConfiguration::new(device, format);
+    ctx_config
+      .alpha_mode(GpuCanvasAlphaMode::Premultiplied)
+      .usage(gpu_texture_usage::RENDER_ATTACHMENT | gpu_texture_usage::COPY_SRC);
+    context.configure(&ctx_config);
   }
   pub async fn new() -> Result<Self, JsValue> {
+    let navigator = window().navigator();
+    if !Reflect::has(&navigator, &JsValue::from_str("gpu"))? {
+      return Err(JsValue::from_str("WebGPU is not supported by this browser"));
+    }
     let canvas = window()
       .document()
       .unwrap()
       .create_element("canvas")?
       .dyn_into::<HtmlCanvasElement>()?;
-    let gpu = window().navigator().gpu();
-    let adapter = JsFuture::from(gpu.request_adapter())
-      .await?
-      .dyn_into::<GpuAdapter>()?;
-    let device = JsFuture::from(adapter.request_device())
-      .await?
-      .dyn_into::<GpuDevice>()?;
+    let gpu = navigator.gpu();
+    let device = Self::request_device(&gpu).await?;
     let context = canvas
       .get_context("webgpu")?
-      .unwrap()
+      .ok_or_else(|| JsValue::from_str("Couldn't get a WebGPU canvas context"))?
       .dyn_into::<GpuCanvasContext>()?;
+    let status = Rc::new(RefCell::new(DeviceStatus::Ready));
+    Self::watch_device(&device, status.clone());
     let (width, height) = get_window_dimension();
     canvas.set_width(width);
     canvas.set_height(height);
-    let mut ctx_config = GpuCanvasConfiguration::new(&device, gpu.get_preferred_canvas_format());
-    ctx_config.alpha_mode(GpuCanvasAlphaMode::Premultiplied);
-    context.configure(&ctx_config);
+    let format = gpu.get_preferred_canvas_format();
+    Self::configure(&context, &device, format);
     let mut color_attachment = GpuRenderPassColorAttachment::new(
       GpuLoadOp::Clear,
       GpuStoreOp::Store,
@@ -100,104 +342,117 @@ impl Renderer {
       })
       .unwrap(),
     );
-    let (depth_texture, depth_attachment) = Self::create_depth_texture(&device, width, height);
-    let mut render_pass_descriptor =
-      GpuRenderPassDescriptor::new(&iter_to_array(&[JsValue::from(&color_attachment)]));
-    render_pass_descriptor.depth_stencil_attachment(&depth_attachment);
-    let (pipeline, pipeline_cubebox) = {
-      let shader =
-        device.create_shader_module(&GpuShaderModuleDescriptor::new(include_str!("shader.wgsl")));
-      let position_attribute_description =
-        GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 0);
-      let vertex_color_attribute_description =
-        GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 1);
-      let tex_coords_attribute_description =
-        GpuVertexAttribute::new(GpuVertexFormat::Float32x2, 0., 2);
-      let mut vertex_state = GpuVertexState::new(&shader);
-      vertex_state.entry_point("vs_main");
-      vertex_state.buffers(&iter_to_array(&[
-        GpuVertexBufferLayout::new(4. * 3., &iter_to_array(&[position_attribute_description])),
-        GpuVertexBufferLayout::new(
-          4. * 3.,
-          &iter_to_array(&[vertex_color_attribute_description]),
-        ),
-        GpuVertexBufferLayout::new(4. * 2., &iter_to_array(&[tex_coords_attribute_description])),
-      ]));
-      let mut fragment_state = GpuFragmentState::new(
-        &shader,
-        &iter_to_array(&[GpuColorTargetState::new(gpu.get_preferred_canvas_format())]),
-      );
-      fragment_state.entry_point("fs_main");
-      let pipeline = device.create_render_pipeline(
-        GpuRenderPipelineDescriptor::new(&"auto".into(), &vertex_state)
-          .label("Defualt Render pipeline")
-          .fragment(&fragment_state)
-          .primitive(
-            GpuPrimitiveState::new()
-              .front_face(GpuFrontFace::Ccw)
-              .cull_mode(GpuCullMode::Back)
-              .topology(GpuPrimitiveTopology::TriangleList),
-          )
-          .depth_stencil(
-            GpuDepthStencilState::new(GpuTextureFormat::Depth24plusStencil8)
-              .depth_compare(GpuCompareFunction::Less)
-              .depth_write_enabled(true),
-          ),
-      );
-      let cubemap_shader = device.create_shader_module(&GpuShaderModuleDescriptor::new(
-        include_str!("shader_cube.wgsl"),
-      ));
-      let position_attribute_description =
-        GpuVertexAttribute::new(GpuVertexFormat::Float32x3, 0., 0);
-      let tex_coords_attribute_description =
-        GpuVertexAttribute::new(GpuVertexFormat::Float32x2, 0., 1);
-      let mut vertex_state = GpuVertexState::new(&cubemap_shader);
-      vertex_state.entry_point("vs_main");
-      vertex_state.buffers(&iter_to_array(&[
-        GpuVertexBufferLayout::new(4. * 3., &iter_to_array(&[position_attribute_description])),
-        GpuVertexBufferLayout::new(4. * 2., &iter_to_array(&[tex_coords_attribute_description])),
-      ]));
-      let mut fragment_state = GpuFragmentState::new(
-        &cubemap_shader,
-        &iter_to_array(&[GpuColorTargetState::new(gpu.get_preferred_canvas_format())]),
-      );
-      fragment_state.entry_point("fs_main");
-      let pipeline_cubemap = device.create_render_pipeline(
-        GpuRenderPipelineDescriptor::new(&"auto".into(), &vertex_state)
-          .label("Cubemap Render pipeline")
-          .fragment(&fragment_state)
-          .primitive(
-            GpuPrimitiveState::new()
-              .front_face(GpuFrontFace::Ccw)
-              .cull_mode(GpuCullMode::Front)
-              .topology(GpuPrimitiveTopology::TriangleList),
-          )
-          .depth_stencil(
-            GpuDepthStencilState::new(GpuTextureFormat::Depth24plusStencil8)
-              .depth_compare(GpuCompareFunction::Less)
-              .depth_write_enabled(true),
-          ),
-      );
-      (pipeline, pipeline_cubemap)
-    };
-    let mut sampler_desc = GpuSamplerDescriptor::new();
-    sampler_desc.address_mode_u(GpuAddressMode::Repeat);
-    sampler_desc.address_mode_v(GpuAddressMode::Repeat);
-    sampler_desc.mag_filter(GpuFilterMode::Linear);
-    let sampler = device.create_sampler_with_descriptor(&sampler_desc);
+    let layouts = Layouts::new(&device);
+    let standard = Self::create_pipelines(&device, &layouts, format, DepthMode::Standard);
+    let reverse_z = Self::create_pipelines(&device, &layouts, format, DepthMode::ReverseZ);
+    let sampler = Self::create_sampler(&device);
+    let timer = GpuTimer::new(&device);
+    let lines = LineRenderer::new(&device, format);
     Ok(Self {
       canvas,
       context,
+      gpu,
+      format,
       device,
-      depth_texture,
-      depth_attachment,
+      status,
       color_attachment,
-      pipeline,
-      pipeline_cubebox,
-      render_pass_descriptor,
+      depth_attachments: Vec::new(),
+      layouts,
+      standard,
+      reverse_z,
       sampler,
+      captures: Vec::new(),
+      timer,
+      lines,
+      counts: Cell::new(RenderStats::default()),
+      frame_stats: RenderStats::default(),
     })
   }
+  pub fn is_ready(&self) -> bool {
+    matches!(*self.status.borrow(), DeviceStatus::Ready)
+  }
+  // The device was lost and no new one could be requested
+  pub fn has_failed(&self) -> bool {
+    matches!(*self.status.borrow(), DeviceStatus::Failed)
+  }
+  // Returns true once a lost device has been replaced and meshes need to be restored
+  pub fn poll_device(&mut self) -> bool {
+    let status = std::mem::replace(&mut *self.status.borrow_mut(), DeviceStatus::Ready);
+    match status {
+      DeviceStatus::Lost => {
+        *self.status.borrow_mut() = DeviceStatus::Requesting;
+        // No frame will be read back from the lost device, so don't leave captures waiting
+        let lost = Err(JsValue::from_str("GPU device was lost"));
+        for capture in self.captures.drain(..) {
+          capture.resolve(&lost);
+        }
+        let gpu = self.gpu.clone();
+        let status = self.status.clone();
+        spawn_local(async move {
+          match Self::request_device(&gpu).await {
+            Ok(device) => {
+              Self::watch_device(&device, status.clone());
+              *status.borrow_mut() = DeviceStatus::Recovered(device);
+            }
+            Err(err) => {
+              log!("Couldn't recover GPU device", err);
+              *status.borrow_mut() = DeviceStatus::Failed;
+            }
+          }
+        });
+        false
+      }
+      DeviceStatus::Recovered(device) => {
+        self.restore(device);
+        true
+      }
+      status => {
+        *self.status.borrow_mut() = status;
+        false
+      }
+    }
+  }
+  fn restore(&mut self, device: GpuDevice) {
+    Self::configure(&self.context, &device, self.format);
+    self.sampler = Self::create_sampler(&device);
+    self.timer = GpuTimer::new(&device);
+    self.layouts = Layouts::new(&device);
+    self.standard =
+      Self::create_pipelines(&device, &self.layouts, self.format, DepthMode::Standard);
+    self.reverse_z =
+      Self::create_pipelines(&device, &self.layouts, self.format, DepthMode::ReverseZ);
+    self.lines = LineRenderer::new(&device, self.format);
+    self.depth_attachments.clear();
+    self.device = device;
+  }
+  fn pipelines(&self, depth_mode: DepthMode) -> &Pipelines {
+    match depth_mode {
+      DepthMode::Standard => &self.standard,
+      DepthMode::ReverseZ => &self.reverse_z,
+    }
+  }
+  fn depth_attachment(&mut self, depth_mode: DepthMode) -> GpuRenderPassDepthStencilAttachment {
+    if let Some((_, attachment)) = self
+      .depth_attachments
+      .iter()
+      .find(|(mode, _)| *mode == depth_mode)
+    {
+      return attachment.clone();
+    }
+    let attachment = Self::create_depth_texture(
+      &self.device,
+      self.canvas.width(),
+      self.canvas.height(),
+      depth_mode,
+    );
+    self
+      .depth_attachments
+      .push((depth_mode, attachment.clone()));
+    attachment
+  }
+  pub fn format(&self) -> GpuTextureFormat {
+    self.format
+  }
   pub fn texture_sampler(&self) -> &GpuSampler {
     &self.sampler
   }
@@ -207,39 +462,118 @@ impl Renderer {
   pub fn device(&self) -> &GpuDevice {
     &self.device
   }
-  pub fn pipeline(&self) -> &GpuRenderPipeline {
-    &self.pipeline
+  // Layout of a mesh's uniforms, bind group 0
+  pub fn uniform_layout(&self) -> &GpuBindGroupLayout {
+    &self.layouts.uniforms
   }
-  pub fn pipeline_cubebox(&self) -> &GpuRenderPipeline {
-    &self.pipeline_cubebox
+  // Layout of a mesh's sampler and texture, bind group 1
+  pub fn texture_layout(&self, material_type: MaterialType) -> &GpuBindGroupLayout {
+    if material_type == MaterialType::CubeMap {
+      &self.layouts.cube_texture
+    } else {
+      &self.layouts.texture
+    }
   }
-  pub fn render(&mut self, meshes: &[Mesh], models: &[Similarity3<f32>], viewport: &Viewport) {
+  // Each viewport gets its own pass and submit, so the per-mesh uniforms written for one
+  // camera can't be overwritten by the next before the GPU has used them
+  pub fn render(
+    &mut self,
+    meshes: &[Mesh],
+    models: &[Matrix4<f32>],
+    lines: &DebugLines,
+    viewports: &[&Viewport],
+  ) {
+    if !self.is_ready() {
+      return;
+    }
+    let frame = self.context.get_current_texture();
+    self.color_attachment.view(&frame.create_view());
+    for (i, viewport) in viewports.iter().enumerate() {
+      let first = i == 0;
+      let last = i + 1 == viewports.len();
+      // Later viewports draw over the earlier ones instead of clearing them
+      self.color_attachment.load_op(if first {
+        GpuLoadOp::Clear
+      } else {
+        GpuLoadOp::Load
+      });
+      // The depth buffer has to agree with how the projection maps depth
+      let depth = self.depth_attachment(viewport.projection().depth_mode());
+      let pass = Pass {
+        color: self.color_attachment.clone(),
+        depth,
+        width: self.canvas.width(),
+        height: self.canvas.height(),
+        lines: Some(lines),
+        // Timestamp writes need at least one index, so passes in between get none
+        timestamps: (first || last).then_some((first, last)),
+        target: None,
+      };
+      self.render_viewport(meshes, models, viewport, pass);
+    }
+    self.frame_stats = self.counts.take();
+    if !self.captures.is_empty() {
+      self.read_frame(&frame);
+    }
+  }
+  // Renders the viewport's region of the target; call it before `render` so the frame's
+  // stats include it. Meshes that sample the target itself are left out, ones sampling another
+  // target see whatever that target holds when this pass runs
+  pub fn render_to(
+    &mut self,
+    target: &RenderTarget,
+    meshes: &[Mesh],
+    models: &[Matrix4<f32>],
+    viewport: &Viewport,
+  ) {
+    if !self.is_ready() {
+      return;
+    }
+    let (color, depth) = target.attachments(&self.device, viewport.projection().depth_mode());
+    let pass = Pass {
+      color,
+      depth,
+      width: target.width(),
+      height: target.height(),
+      lines: None,
+      timestamps: None,
+      target: Some(target),
+    };
+    self.render_viewport(meshes, models, viewport, pass);
+  }
+  fn render_viewport(
+    &mut self,
+    meshes: &[Mesh],
+    models: &[Matrix4<f32>],
+    viewport: &Viewport,
+    pass: Pass,
+  ) {
     let queue = self.device.queue();
-    self
-      .color_attachment
-      .view(&self.context.get_current_texture().create_view());
-    self
-      .render_pass_descriptor
-      .color_attachments(&iter_to_array(&[JsValue::from(&self.color_attachment)]));
-    self
-      .render_pass_descriptor
-      .depth_stencil_attachment(&self.depth_attachment);
+    let mut render_pass_descriptor =
+      GpuRenderPassDescriptor::new(&iter_to_array(&[JsValue::from(&pass.color)]));
+    render_pass_descriptor.depth_stencil_attachment(&pass.depth);
+    let timer = self.timer.as_ref().zip(pass.timestamps);
+    if let Some((timer, (first, last))) = timer {
+      render_pass_descriptor.timestamp_writes(&timer.timestamp_writes(first, last));
+    }
     let command_encoder = self.device.create_command_encoder();
-    let pass_encoder = command_encoder.begin_render_pass(&self.render_pass_descriptor);
-    pass_encoder.set_viewport(
-      0.,
-      0.,
-      self.canvas.width() as f32,
-      self.canvas.height() as f32,
-      0.,
-      1.,
-    );
-    pass_encoder.set_scissor_rect(0, 0, self.canvas.width(), self.canvas.height());
+    let pass_encoder = command_encoder.begin_render_pass(&render_pass_descriptor);
+    let (x, y, width, height) = viewport.region().pixels(pass.width, pass.height);
+    pass_encoder.set_viewport(x as f32, y as f32, width as f32, height as f32, 0., 1.);
+    pass_encoder.set_scissor_rect(x, y, width, height);
+    let wireframe = pass.lines.is_some_and(|lines| lines.wireframe);
+    let pipelines = self.pipelines(viewport.projection().depth_mode());
     for (mesh, model) in meshes.iter().zip(models.iter()) {
+      if wireframe && mesh.material_type != MaterialType::CubeMap {
+        continue;
+      }
+      if pass.target.is_some_and(|target| mesh.samples(target)) {
+        continue;
+      }
       if mesh.material_type == MaterialType::CubeMap {
-        pass_encoder.set_pipeline(&self.pipeline_cubebox);
+        pass_encoder.set_pipeline(&pipelines.cubemap);
       } else {
-        pass_encoder.set_pipeline(&self.pipeline);
+        pass_encoder.set_pipeline(&pipelines.mesh);
       }
       pass_encoder.set_vertex_buffer(0, Some(&mesh.vertex_buffer));
 
@@ -257,11 +591,12 @@ impl Renderer {
       pass_encoder.set_bind_group(1, Some(&mesh.texture_bind_group));
 
       if matches!(mesh.material_type, MaterialType::CubeMap) {
-        let mvp = viewport.view_cube() * model.to_homogeneous();
+        let mvp = viewport.view_cube() * model;
         let uniforms = Float32Array::from(mvp.as_slice());
         queue.write_buffer_with_u32_and_buffer_source(&mesh.uniform_buffer, 0, &uniforms);
+        self.count_upload(uniforms.byte_length() as usize);
       } else {
-        let mvp = viewport.view_proj() * model.to_homogeneous();
+        let mvp = viewport.model_view_proj(model);
         let Color { r, g, b, a } = mesh.color;
         let mut uniforms: Vec<f32> = mvp.into_iter().copied().collect();
         uniforms.push(r);
@@ -269,22 +604,127 @@ impl Renderer {
         uniforms.push(b);
         uniforms.push(a);
         uniforms.push(mesh.material_type as u32 as f32);
+        // The highlight vec4 is aligned to 16 bytes
+        uniforms.extend([0.; 3]);
+        if let Some(Color { r, g, b, a }) = mesh.highlight {
+          uniforms.extend([r, g, b, a]);
+        } else {
+          uniforms.extend([0.; 4]);
+        }
         let uniforms = Float32Array::from(&uniforms[..]);
         queue.write_buffer_with_u32_and_buffer_source(&mesh.uniform_buffer, 0, &uniforms);
+        self.count_upload(uniforms.byte_length() as usize);
       }
       pass_encoder.set_index_buffer(&mesh.index_buffer, GpuIndexFormat::Uint16);
       pass_encoder.draw_indexed(mesh.index_count);
+      let mut counts = self.counts.get();
+      counts.draw_calls += 1;
+      counts.triangles += mesh.index_count / 3;
+      self.counts.set(counts);
+    }
+    if let Some(lines) = pass.lines {
+      let uploaded = self
+        .lines
+        .draw(&self.device, &pass_encoder, lines, viewport);
+      if uploaded > 0 {
+        self.count_upload(uploaded);
+      }
     }
     pass_encoder.end();
+    let timer = timer
+      .filter(|(timer, (_, last))| *last && timer.resolve(&command_encoder))
+      .map(|(timer, _)| timer);
     queue.submit(&iter_to_array(&[command_encoder.finish()]));
+    if let Some(timer) = timer {
+      timer.read();
+    }
+  }
+  pub fn capture_frame(&mut self) -> impl Future<Output = Result<Image, JsValue>> {
+    let (capture, image) = Capture::new(0);
+    self.captures.push(capture);
+    image
+  }
+  // One future per frame, oldest first; awaiting and encoding them one at a time keeps only
+  // one frame in wasm memory, the rest wait as ImageData on the JS side
+  pub fn capture_sequence(
+    &mut self,
+    frames: u32,
+  ) -> Vec<impl Future<Output = Result<Image, JsValue>>> {
+    (0..frames)
+      .map(|delay| {
+        let (capture, image) = Capture::new(delay);
+        self.captures.push(capture);
+        image
+      })
+      .collect()
+  }
+  pub fn frame_stats(&self) -> RenderStats {
+    self.frame_stats
+  }
+  // The newest GPU measurement, or None if no readback completed since the last call
+  pub fn gpu_time(&self) -> Option<f64> {
+    self.timer.as_ref().and_then(|timer| timer.elapsed())
+  }
+  fn count_upload(&self, bytes: usize) {
+    let mut counts = self.counts.get();
+    counts.uploads += 1;
+    counts.upload_bytes += bytes;
+    self.counts.set(counts);
+  }
+  pub fn is_capturing(&self) -> bool {
+    !self.captures.is_empty()
+  }
+  fn read_frame(&mut self, frame: &GpuTexture) {
+    let (ready, waiting): (Vec<_>, Vec<_>) = self
+      .captures
+      .drain(..)
+      .partition(|capture| capture.delay == 0);
+    self.captures = waiting
+      .into_iter()
+      .map(|mut capture| {
+        capture.delay -= 1;
+        capture
+      })
+      .collect();
+    if ready.is_empty() {
+      return;
+    }
+    let (width, height) = (frame.width(), frame.height());
+    let bytes_per_row = (width * 4 + 255) & !255;
+    let buffer = self.device.create_buffer(&GpuBufferDescriptor::new(
+      (bytes_per_row * height) as f64,
+      gpu_buffer_usage::MAP_READ | gpu_buffer_usage::COPY_DST,
+    ));
+    let command_encoder = self.device.create_command_encoder();
+    command_encoder.copy_texture_to_buffer_with_u32_sequence(
+      &GpuImageCopyTexture::new(frame),
+      GpuImageCopyBuffer::new(&buffer).bytes_per_row(bytes_per_row),
+      &iter_to_array([width, height]),
+    );
+    self
+      .device
+      .queue()
+      .submit(&iter_to_array(&[command_encoder.finish()]));
+    let bgra = self.format == GpuTextureFormat::Bgra8unorm;
+    spawn_local(async move {
+      let image = async {
+        JsFuture::from(buffer.map_async(gpu_map_mode::READ)).await?;
+        let padded = Uint8Array::new(&buffer.get_mapped_range()).to_vec();
+        buffer.unmap();
+        buffer.destroy();
+        Image::from_padded(&padded, width, height, bytes_per_row, bgra).to_image_data()
+      }
+      .await;
+      for capture in ready {
+        capture.resolve(&image);
+      }
+    });
   }
   pub fn resize(&mut self) {
     let (width, height) = get_window_dimension();
     self.canvas.set_width(width);
     self.canvas.set_height(height);
-    let (depth_texture, depth_attachment) = Self::create_depth_texture(&self.device, width, height);
-    self.depth_texture = depth_texture;
-    self.depth_attachment = depth_attachment;
+    self.depth_attachments.clear();
   }
   pub fn create_buffer(&self, data: &[f32]) -> GpuBuffer {
     let byte_len = data.len() * 4;
@@ -295,6 +735,7 @@ impl Renderer {
     let write_array = Float32Array::new(&buffer.get_mapped_range());
     write_array.set(&Float32Array::from(data), 0);
     buffer.unmap();
+    self.count_upload(byte_len);
     buffer
   }
   pub fn create_index_buffer(&self, data: &[u16]) -> GpuBuffer {
@@ -310,6 +751,7 @@ impl Renderer {
     let write_array = Uint16Array::new(&buffer.get_mapped_range());
     write_array.set(&Uint16Array::from(data), 0);
     buffer.unmap();
+    self.count_upload(data.len() * 2);
     buffer
   }
   pub fn create_texture(&self, rect: &Rect, num_images: u32) -> GpuTexture {
@@ -365,6 +807,27 @@ impl Color {
   pub fn rgb(r: f32, g: f32, b: f32) -> Self {
     Self { r, g, b, a: 1. }
   }
+  // Hue in degrees, as used by rapier's debug render style
+  pub fn hsla([h, s, l, a]: [f32; 4]) -> Self {
+    let c = (1. - (2. * l - 1.).abs()) * s;
+    let h = h.rem_euclid(360.) / 60.;
+    let x = c * (1. - (h % 2. - 1.).abs());
+    let (r, g, b) = match h as u32 {
+      0 => (c, x, 0.),
+      1 => (x, c, 0.),
+      2 => (0., c, x),
+      3 => (0., x, c),
+      4 => (x, 0., c),
+      _ => (c, 0., x),
+    };
+    let m = l - c / 2.;
+    Self {
+      r: r + m,
+      g: g + m,
+      b: b + m,
+      a,
+    }
+  }
 }
 
 #[derive(Serialize)]
@@ -1,20 +1,65 @@
-use crate::Mesh;
-use nalgebra::{vector, Similarity, Similarity3};
+use crate::collider::{ColliderShape, ColliderSource};
+use crate::debug::{DebugLines, DebugMode};
+use crate::events::{ContactEvent, ContactEvents};
+use crate::gravity::Gravity;
+use crate::joint::Joint;
+use crate::mesh::MaterialType;
+use crate::query::{PointHit, RayHit, ShapeHit};
+use crate::renderer::Color;
+use crate::snapshot::History;
+use crate::{Mesh, Renderer};
+use nalgebra::{vector, Isometry3, Matrix4, Point3, Translation3, UnitQuaternion, Vector3};
 use rapier3d::{
-  dynamics::RigidBodyHandle,
+  control::{EffectiveCharacterMovement, KinematicCharacterController},
+  dynamics::{ImpulseJointHandle, MultibodyJointHandle, RigidBodyHandle},
   geometry::BroadPhaseMultiSap,
+  parry::query::{ShapeCastOptions, ShapeCastStatus},
+  pipeline::{DebugRenderPipeline, QueryFilter, QueryPipeline},
   prelude::{
-    CCDSolver, Collider, ColliderHandle, ColliderSet, ImpulseJointSet, IntegrationParameters,
-    IslandManager, MultibodyJointSet, NarrowPhase, PhysicsPipeline, RigidBody, RigidBodySet,
+    ActiveEvents, CCDSolver, Collider, ColliderHandle, ColliderSet, ImpulseJointSet,
+    IntegrationParameters, InteractionGroups, IslandManager, MultibodyJointSet, NarrowPhase,
+    PhysicsPipeline, Ray, RigidBody, RigidBodySet, RigidBodyType, Shape,
   },
 };
 
+type PhysicsState = (
+  RigidBodySet,
+  ColliderSet,
+  ImpulseJointSet,
+  MultibodyJointSet,
+  IslandManager,
+  BroadPhaseMultiSap,
+  NarrowPhase,
+  CCDSolver,
+  QueryPipeline,
+  IntegrationParameters,
+);
+
+type EntityTable = (
+  Vec<String>,
+  Vec<RigidBodyHandle>,
+  Vec<Option<(ColliderHandle, ColliderSource)>>,
+  Vec<Vector3<f32>>,
+  Vec<Option<Gravity>>,
+  Vec<Isometry3<f32>>,
+  Gravity,
+  f32,
+  u64,
+);
+
 pub struct Scene {
   ids: Vec<String>,
   meshes: Vec<Mesh>,
   r_handles: Vec<RigidBodyHandle>,
-  c_handles: Vec<ColliderHandle>,
-  scales: Vec<f32>,
+  colliders: Vec<Option<(ColliderHandle, ColliderSource)>>,
+  scales: Vec<Vector3<f32>>,
+  gravities: Vec<Option<Gravity>>,
+  gravity: Gravity,
+  previous: Vec<Isometry3<f32>>,
+  accumulator: f32,
+  max_substeps: u32,
+  step: u64,
+  history: Option<History>,
   rigid_body_set: RigidBodySet,
   collider_set: ColliderSet,
   integration_parameters: IntegrationParameters,
@@ -26,8 +71,20 @@ pub struct Scene {
 
   multibody_joint_set: MultibodyJointSet,
   ccd_solver: CCDSolver,
+  query_pipeline: QueryPipeline,
+  debug_mode: DebugMode,
+  debug_pipeline: DebugRenderPipeline,
+  events: ContactEvents,
+  selected: Option<usize>,
 }
 
+const SELECTION: Color = Color {
+  r: 1.,
+  g: 0.8,
+  b: 0.2,
+  a: 0.4,
+};
+
 impl Default for Scene {
   fn default() -> Self {
     Self::new()
@@ -50,8 +107,15 @@ impl Scene {
       ids: Vec::new(),
       meshes: Vec::new(),
       r_handles: Vec::new(),
-      c_handles: Vec::new(),
+      colliders: Vec::new(),
       scales: Vec::new(),
+      gravities: Vec::new(),
+      gravity: Gravity::None,
+      previous: Vec::new(),
+      accumulator: 0.,
+      max_substeps: 8,
+      step: 0,
+      history: None,
       rigid_body_set: RigidBodySet::new(),
       collider_set,
       integration_parameters,
@@ -62,6 +126,11 @@ impl Scene {
       impulse_joint_set,
       multibody_joint_set,
       ccd_solver,
+      query_pipeline: QueryPipeline::new(),
+      debug_mode: DebugMode::default(),
+      debug_pipeline: DebugRenderPipeline::default(),
+      events: ContactEvents::new(),
+      selected: None,
     }
   }
 
@@ -70,21 +139,62 @@ impl Scene {
   }
 
   pub fn add_w_scale(&mut self, name: &str, mesh: Mesh, body: RigidBody, scale: f32) {
+    self.previous.push(*body.position());
     let handle = self.rigid_body_set.insert(body);
     self.ids.push(name.to_owned());
     self.meshes.push(mesh);
     self.r_handles.push(handle);
-    self.scales.push(scale);
+    self.colliders.push(None);
+    self.scales.push(Vector3::repeat(scale));
+    self.gravities.push(None);
   }
 
+  // The collider is in mesh units and gets scaled along with the mesh. Shapes that can't be
+  // scaled, like heightfields, are used as given.
   pub fn add_w_scale_collider(
     &mut self,
     name: &str,
     mesh: Mesh,
     body: RigidBody,
-    collider: Collider,
+    mut collider: Collider,
     scale: f32,
   ) {
+    let scale = Vector3::repeat(scale);
+    let source = ColliderSource::Shape(collider.shared_shape().clone(), *collider.position());
+    if let Some((shape, position)) = source.scaled(mesh.geometry(), &scale) {
+      collider.set_shape(shape);
+      collider.set_position(position);
+    }
+    self.insert_w_collider(name, mesh, body, collider, source, scale);
+  }
+
+  // Generates the collider from the mesh geometry at the render scale, `None` if that fails
+  pub fn add_w_scale_shape(
+    &mut self,
+    name: &str,
+    mesh: Mesh,
+    body: RigidBody,
+    shape: ColliderShape,
+    scale: f32,
+  ) -> Option<()> {
+    let scale = Vector3::repeat(scale);
+    let collider = shape.from_geometry(mesh.geometry(), &scale)?.build();
+    let source = ColliderSource::Geometry(shape);
+    self.insert_w_collider(name, mesh, body, collider, source, scale);
+    Some(())
+  }
+
+  fn insert_w_collider(
+    &mut self,
+    name: &str,
+    mesh: Mesh,
+    body: RigidBody,
+    mut collider: Collider,
+    source: ColliderSource,
+    scale: Vector3<f32>,
+  ) {
+    collider.set_active_events(collider.active_events() | ActiveEvents::COLLISION_EVENTS);
+    self.previous.push(*body.position());
     let r_handle = self.rigid_body_set.insert(body);
     let c_handle =
       self
@@ -93,29 +203,309 @@ impl Scene {
     self.ids.push(name.to_owned());
     self.meshes.push(mesh);
     self.r_handles.push(r_handle);
-    self.c_handles.push(c_handle);
+    self.colliders.push(Some((c_handle, source)));
     self.scales.push(scale);
+    self.gravities.push(None);
+    // Make the collider queryable before the next step
+    self.query_pipeline.update(&self.collider_set);
   }
 
-  pub fn simiarities(&self) -> Vec<Similarity3<f32>> {
-    self
-      .r_handles
-      .iter()
-      .zip(self.scales.iter())
-      .map(|(handle, scale)| {
-        let body = self.rigid_body_set.get(*handle).unwrap();
-        Similarity::from_isometry(*body.position(), *scale)
+  pub fn scale(&self, key: &str) -> Option<Vector3<f32>> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    Some(self.scales[key])
+  }
+
+  // Rescales the mesh and its collider together, `None` if the collider can't take that scale
+  pub fn set_scale(&mut self, key: &str, scale: Vector3<f32>) -> Option<()> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    if let Some((handle, source)) = &self.colliders[key] {
+      let (shape, position) = source.scaled(self.meshes[key].geometry(), &scale)?;
+      let collider = self.collider_set.get_mut(*handle)?;
+      collider.set_shape(shape);
+      collider.set_position_wrt_parent(position);
+      self.query_pipeline.update(&self.collider_set);
+    }
+    self.scales[key] = scale;
+    Some(())
+  }
+
+  pub fn models(&self) -> Vec<Matrix4<f32>> {
+    (0..self.r_handles.len())
+      .map(|i| {
+        self.interpolated(i).to_homogeneous() * Matrix4::new_nonuniform_scaling(&self.scales[i])
       })
       .collect()
   }
 
+  // Blends the last two physics states by how far the accumulator is into the next step
+  fn interpolated(&self, index: usize) -> Isometry3<f32> {
+    let body = self.rigid_body_set.get(self.r_handles[index]).unwrap();
+    let alpha = self.accumulator / self.integration_parameters.dt;
+    self.previous[index].lerp_slerp(body.position(), alpha)
+  }
+
+  pub fn get_isometry(&self, key: &str) -> Option<Isometry3<f32>> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    Some(self.interpolated(key))
+  }
+
+  pub fn timestep(&self) -> f32 {
+    self.integration_parameters.dt
+  }
+  pub fn set_timestep(&mut self, dt: f32, max_substeps: u32) {
+    self.integration_parameters.dt = dt;
+    self.max_substeps = max_substeps;
+  }
+
+  // Advances the simulation by `dt` seconds in fixed steps and returns how many were taken.
+  // `on_step` is called with the timestep before each one, for anything that has to advance
+  // in lockstep with the bodies, like characters. Fails only if history is on and a snapshot
+  // for it can't be taken
+  pub fn update(
+    &mut self,
+    dt: f32,
+    mut on_step: impl FnMut(&mut Self, f32),
+  ) -> bincode::Result<u32> {
+    let step = self.integration_parameters.dt;
+    self.accumulator += dt;
+    let mut steps = 0;
+    while self.accumulator >= step && steps < self.max_substeps {
+      self.accumulator -= step;
+      steps += 1;
+      on_step(self, step);
+      self.physics();
+      if let Some(history) = &self.history {
+        if history.is_due(self.step) {
+          let snapshot = self.snapshot()?;
+          self.history.as_mut().unwrap().push(self.step, snapshot);
+        }
+      }
+    }
+    // Drop the backlog instead of spiralling when we can't keep up
+    if steps == self.max_substeps {
+      self.accumulator = self.accumulator.min(step);
+    }
+    Ok(steps)
+  }
+
   pub fn meshes(&self) -> &Vec<Mesh> {
     &self.meshes
   }
-  pub fn physics(&mut self) {
+  pub fn restore(&mut self, renderer: &Renderer) {
+    for mesh in self.meshes.iter_mut() {
+      mesh.restore(renderer);
+    }
+  }
+  pub fn debug_mode(&self) -> DebugMode {
+    self.debug_mode
+  }
+  pub fn debug_mode_mut(&mut self) -> &mut DebugMode {
+    &mut self.debug_mode
+  }
+  pub fn debug_render(&mut self, lines: &mut DebugLines) {
+    lines.clear();
+    let mode = self.debug_mode;
+    self.debug_pipeline.mode = mode.rapier_mode();
+    self.debug_pipeline.render(
+      lines,
+      &self.rigid_body_set,
+      &self.collider_set,
+      &self.impulse_joint_set,
+      &self.multibody_joint_set,
+      &self.narrow_phase,
+    );
+    if mode.velocities {
+      for handle in self.r_handles.iter() {
+        let body = &self.rigid_body_set[*handle];
+        let from = body.translation();
+        lines.line(
+          (*from).into(),
+          (from + body.linvel()).into(),
+          Color::rgb(1., 1., 0.),
+        );
+      }
+    }
+    if mode.normals || mode.wireframe {
+      for (mesh, model) in self.meshes.iter().zip(self.models()) {
+        if mesh.material_type == MaterialType::CubeMap {
+          continue;
+        }
+        if mode.normals {
+          lines.normals(mesh.geometry(), &model, Color::rgb(0., 0.5, 1.));
+        }
+        if mode.wireframe {
+          lines.edges(mesh.geometry(), &model, Color::rgb(1., 1., 1.));
+        }
+      }
+    }
+    lines.wireframe = mode.wireframe;
+  }
+  pub fn gravity(&self) -> Gravity {
+    self.gravity
+  }
+  pub fn set_gravity(&mut self, gravity: Gravity) {
+    self.gravity = gravity;
+  }
+  // Overrides the scene gravity for one entity, `None` restores the scene gravity
+  pub fn set_body_gravity(&mut self, key: &str, gravity: Option<Gravity>) -> Option<()> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    self.gravities[key] = gravity;
+    Some(())
+  }
+  // Acceleration due to gravity at the entity's center of mass, with its override if it has one
+  pub fn gravity_at(&self, key: &str) -> Option<Vector3<f32>> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    let body = self.rigid_body_set.get(self.r_handles[key])?;
+    let gravity = self.gravities[key].unwrap_or(self.gravity);
+    Some(gravity.acceleration_at(body.center_of_mass()))
+  }
+  // Slides a kinematic entity's collider by `translation`, stopping at obstacles. The body gets
+  // there on the next step.
+  pub fn move_character(
+    &mut self,
+    key: &str,
+    controller: &KinematicCharacterController,
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    push_mass: Option<f32>,
+    dt: f32,
+  ) -> Option<EffectiveCharacterMovement> {
+    let handle = self.body_handle(key)?;
+    let body = self.rigid_body_set.get(handle)?;
+    let collider = self.collider_set.get(*body.colliders().first()?)?;
+    let shape = collider.shared_shape().clone();
+    let position = *collider.position();
+    let filter = QueryFilter::default().exclude_rigid_body(handle);
+    let mut collisions = Vec::new();
+    let movement = controller.move_shape(
+      dt,
+      &self.rigid_body_set,
+      &self.collider_set,
+      &self.query_pipeline,
+      &*shape,
+      &position,
+      translation,
+      filter,
+      |collision| collisions.push(collision),
+    );
+    if let Some(mass) = push_mass {
+      controller.solve_character_collision_impulses(
+        dt,
+        &mut self.rigid_body_set,
+        &self.collider_set,
+        &self.query_pipeline,
+        &*shape,
+        mass,
+        &collisions,
+        filter,
+      );
+    }
+    let body = self.rigid_body_set.get_mut(handle)?;
+    let next = Translation3::from(body.translation() + movement.translation);
+    body.set_next_kinematic_position(Isometry3::from_parts(next, rotation));
+    Some(movement)
+  }
+  // Number of fixed steps simulated so far
+  pub fn step(&self) -> u64 {
+    self.step
+  }
+  // The complete physics state and entity table; meshes are not included, so a snapshot can
+  // only be loaded into a scene with the same entities
+  pub fn snapshot(&self) -> bincode::Result<Vec<u8>> {
+    let physics = (
+      &self.rigid_body_set,
+      &self.collider_set,
+      &self.impulse_joint_set,
+      &self.multibody_joint_set,
+      &self.island_manager,
+      &self.broad_phase,
+      &self.narrow_phase,
+      &self.ccd_solver,
+      &self.query_pipeline,
+      &self.integration_parameters,
+    );
+    let entities = (
+      &self.ids,
+      &self.r_handles,
+      &self.colliders,
+      &self.scales,
+      &self.gravities,
+      &self.previous,
+      self.gravity,
+      self.accumulator,
+      self.step,
+    );
+    bincode::serialize(&(physics, entities))
+  }
+  pub fn load_snapshot(&mut self, snapshot: &[u8]) -> bincode::Result<()> {
+    let (physics, entities): (PhysicsState, EntityTable) = bincode::deserialize(snapshot)?;
+    if entities.0 != self.ids {
+      return Err(serde::de::Error::custom(
+        "snapshot was taken from a scene with different entities",
+      ));
+    }
+    (
+      self.rigid_body_set,
+      self.collider_set,
+      self.impulse_joint_set,
+      self.multibody_joint_set,
+      self.island_manager,
+      self.broad_phase,
+      self.narrow_phase,
+      self.ccd_solver,
+      self.query_pipeline,
+      self.integration_parameters,
+    ) = physics;
+    (
+      _,
+      self.r_handles,
+      self.colliders,
+      self.scales,
+      self.gravities,
+      self.previous,
+      self.gravity,
+      self.accumulator,
+      self.step,
+    ) = entities;
+    Ok(())
+  }
+  // Keeps a snapshot every `interval` steps for the last `seconds` so the scene can be rewound
+  pub fn record_history(&mut self, seconds: f32, interval: u64) {
+    self.history = Some(History::new(seconds, interval, self.timestep()));
+  }
+  pub fn stop_history(&mut self) {
+    self.history = None;
+  }
+  // Goes back to the latest snapshot at least `seconds` old, `None` if history doesn't reach
+  pub fn rewind(&mut self, seconds: f32) -> Option<()> {
+    let steps = (seconds / self.timestep()).round() as u64;
+    let target = self.step.checked_sub(steps)?;
+    let snapshot = self.history.as_mut()?.rewind_to(target)?.to_vec();
+    self.load_snapshot(&snapshot).ok()
+  }
+  fn physics(&mut self) {
+    self.step += 1;
+    for (previous, handle) in self.previous.iter_mut().zip(self.r_handles.iter()) {
+      *previous = *self.rigid_body_set[*handle].position();
+    }
+    // The pipeline runs without gravity, each body gets a step's worth of velocity instead,
+    // which leaves forces added by the user alone. Like pipeline gravity it ignores mass, so
+    // bodies without colliders fall too, and sleeping bodies stay asleep
+    let dt = self.integration_parameters.dt;
+    for (handle, gravity) in self.r_handles.iter().zip(self.gravities.iter()) {
+      let Some(body) = self.rigid_body_set.get_mut(*handle) else {
+        continue;
+      };
+      if !body.is_dynamic() || body.is_sleeping() {
+        continue;
+      }
+      let acceleration = gravity
+        .unwrap_or(self.gravity)
+        .acceleration_at(body.center_of_mass());
+      body.set_linvel(body.linvel() + acceleration * dt, false);
+    }
     self.physics_pipeline.step(
-      // &vector![0., -9.8, 0.],
-      &vector![0., -0., 0.],
+      &vector![0., 0., 0.],
       &self.integration_parameters,
       &mut self.island_manager,
       &mut self.broad_phase,
@@ -125,10 +515,248 @@ impl Scene {
       &mut self.impulse_joint_set,
       &mut self.multibody_joint_set,
       &mut self.ccd_solver,
-      None,
-      &(),
+      Some(&mut self.query_pipeline),
       &(),
+      self.events.collector(),
     );
+    let (ids, r_handles, colliders) = (&self.ids, &self.r_handles, &self.collider_set);
+    let name = |handle| entity_name(ids, r_handles, colliders, handle);
+    self.events.dispatch(|a, b| Some((name(a)?, name(b)?)));
+  }
+  // Events from every step since the last call, oldest first
+  pub fn drain_events(&mut self) -> Vec<ContactEvent> {
+    self.events.drain().collect()
+  }
+  // Calls back on every event between `a` and `b`, or between `a` and anything when `b` is `None`
+  pub fn on_contact(
+    &mut self,
+    a: &str,
+    b: Option<&str>,
+    callback: impl FnMut(&ContactEvent) + 'static,
+  ) {
+    self.events.subscribe(a, b, callback);
+  }
+  // Reports contact forces above `threshold` for the entity's colliders, `None` turns them off
+  pub fn set_contact_force_threshold(&mut self, key: &str, threshold: Option<f32>) -> Option<()> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    let body = self.rigid_body_set.get(self.r_handles[key])?;
+    for handle in body.colliders() {
+      let collider = &mut self.collider_set[*handle];
+      let mut events = collider.active_events();
+      events.set(ActiveEvents::CONTACT_FORCE_EVENTS, threshold.is_some());
+      collider.set_active_events(events);
+      collider.set_contact_force_event_threshold(threshold.unwrap_or(0.));
+    }
+    Some(())
+  }
+  fn collider_name(&self, handle: ColliderHandle) -> Option<String> {
+    entity_name(&self.ids, &self.r_handles, &self.collider_set, handle)
+  }
+  // A filter that ignores the entity itself, e.g. for ground checks below the player
+  pub fn filter_excluding(&self, key: &str) -> QueryFilter<'static> {
+    let filter = QueryFilter::default();
+    match self.ids.iter().position(|p| p == key) {
+      Some(key) => filter.exclude_rigid_body(self.r_handles[key]),
+      None => filter,
+    }
+  }
+  pub fn raycast(
+    &self,
+    origin: Point3<f32>,
+    dir: Vector3<f32>,
+    max_toi: f32,
+    filter: QueryFilter,
+  ) -> Option<RayHit> {
+    let ray = Ray::new(origin, dir);
+    let (handle, hit) = self.query_pipeline.cast_ray_and_get_normal(
+      &self.rigid_body_set,
+      &self.collider_set,
+      &ray,
+      max_toi,
+      true,
+      filter,
+    )?;
+    Some(RayHit {
+      entity: self.collider_name(handle)?,
+      toi: hit.time_of_impact,
+      point: ray.point_at(hit.time_of_impact),
+      normal: hit.normal,
+    })
+  }
+  pub fn cast_shape(
+    &self,
+    position: &Isometry3<f32>,
+    velocity: &Vector3<f32>,
+    shape: &dyn Shape,
+    max_toi: f32,
+    filter: QueryFilter,
+  ) -> Option<ShapeHit> {
+    let options = ShapeCastOptions::with_max_time_of_impact(max_toi);
+    let (handle, hit) = self.query_pipeline.cast_shape(
+      &self.rigid_body_set,
+      &self.collider_set,
+      position,
+      velocity,
+      shape,
+      options,
+      filter,
+    )?;
+    Some(ShapeHit {
+      entity: self.collider_name(handle)?,
+      toi: hit.time_of_impact,
+      point: hit.witness1,
+      normal: *hit.normal1,
+      penetrating: hit.status == ShapeCastStatus::PenetratingOrWithinTargetDist,
+    })
+  }
+  pub fn intersections_with_point(&self, point: Point3<f32>, filter: QueryFilter) -> Vec<String> {
+    let mut entities = Vec::new();
+    self.query_pipeline.intersections_with_point(
+      &self.rigid_body_set,
+      &self.collider_set,
+      &point,
+      filter,
+      |handle| {
+        entities.extend(self.collider_name(handle));
+        true
+      },
+    );
+    entities
+  }
+  pub fn project_point(
+    &self,
+    point: Point3<f32>,
+    solid: bool,
+    filter: QueryFilter,
+  ) -> Option<PointHit> {
+    let (handle, projection) = self.query_pipeline.project_point(
+      &self.rigid_body_set,
+      &self.collider_set,
+      &point,
+      solid,
+      filter,
+    )?;
+    Some(PointHit {
+      entity: self.collider_name(handle)?,
+      point: projection.point,
+      inside: projection.is_inside,
+    })
+  }
+  fn body_handle(&self, key: &str) -> Option<RigidBodyHandle> {
+    let key = self.ids.iter().position(|p| p == key)?;
+    Some(self.r_handles[key])
+  }
+  // `None` if an entity doesn't exist or the joint has options its kind doesn't take
+  pub fn connect(&mut self, a: &str, b: &str, joint: &Joint) -> Option<ImpulseJointHandle> {
+    let (a, b) = (self.body_handle(a)?, self.body_handle(b)?);
+    Some(self.impulse_joint_set.insert(a, b, joint.build()?, true))
+  }
+  // Reduced-coordinate joint that can't drift apart, better for long chains and ragdolls.
+  // `None` if it would close a loop in an existing multibody, or like `connect`
+  pub fn connect_multibody(
+    &mut self,
+    a: &str,
+    b: &str,
+    joint: &Joint,
+  ) -> Option<MultibodyJointHandle> {
+    let (a, b) = (self.body_handle(a)?, self.body_handle(b)?);
+    self.multibody_joint_set.insert(a, b, joint.build()?, true)
+  }
+  // Replaces the limits, motor and anchors of an existing joint, e.g. to drive a motor
+  pub fn update_joint(&mut self, handle: ImpulseJointHandle, joint: &Joint) -> Option<()> {
+    let impulse_joint = self.impulse_joint_set.get_mut(handle)?;
+    impulse_joint.data = joint.build()?;
+    let bodies = [impulse_joint.body1, impulse_joint.body2];
+    for body in bodies {
+      if let Some(body) = self.rigid_body_set.get_mut(body) {
+        body.wake_up(true);
+      }
+    }
+    Some(())
+  }
+  pub fn disconnect(&mut self, handle: ImpulseJointHandle) {
+    self.impulse_joint_set.remove(handle, true);
+  }
+  pub fn disconnect_multibody(&mut self, handle: MultibodyJointHandle) {
+    self.multibody_joint_set.remove(handle, true);
+  }
+  // Switching to dynamic keeps the current velocities, use `teleport` to start from rest
+  pub fn set_body_type(&mut self, key: &str, body_type: RigidBodyType) -> Option<()> {
+    let handle = self.body_handle(key)?;
+    self
+      .rigid_body_set
+      .get_mut(handle)?
+      .set_body_type(body_type, true);
+    Some(())
+  }
+  // Moves the entity without sweeping through what's in between, stopping it and skipping the
+  // interpolation from where it was
+  pub fn teleport(&mut self, key: &str, position: Isometry3<f32>) -> Option<()> {
+    let index = self.ids.iter().position(|p| p == key)?;
+    let body = self.rigid_body_set.get_mut(self.r_handles[index])?;
+    body.set_position(position, true);
+    if body.is_kinematic() {
+      body.set_next_kinematic_position(position);
+    }
+    body.set_linvel(Vector3::zeros(), true);
+    body.set_angvel(Vector3::zeros(), true);
+    self.previous[index] = position;
+    self.query_pipeline.update(&self.collider_set);
+    Some(())
+  }
+  // Disabled entities are left out of the simulation and queries but still rendered
+  pub fn set_enabled(&mut self, key: &str, enabled: bool) -> Option<()> {
+    let handle = self.body_handle(key)?;
+    self.rigid_body_set.get_mut(handle)?.set_enabled(enabled);
+    Some(())
+  }
+  pub fn is_enabled(&self, key: &str) -> Option<bool> {
+    Some(self.get_body(key)?.is_enabled())
+  }
+  pub fn sleep(&mut self, key: &str) -> Option<()> {
+    self.get_body_mut(key)?.sleep();
+    Some(())
+  }
+  pub fn wake(&mut self, key: &str) -> Option<()> {
+    self.get_body_mut(key)?.wake_up(true);
+    Some(())
+  }
+  pub fn is_sleeping(&self, key: &str) -> Option<bool> {
+    Some(self.get_body(key)?.is_sleeping())
+  }
+  // Which entities this one touches at all
+  pub fn set_collision_groups(&mut self, key: &str, groups: InteractionGroups) -> Option<()> {
+    let body = self.rigid_body_set.get(self.body_handle(key)?)?;
+    for handle in body.colliders() {
+      self.collider_set[*handle].set_collision_groups(groups);
+    }
+    Some(())
+  }
+  // Which entities this one pushes against; the others still raise contact events
+  pub fn set_solver_groups(&mut self, key: &str, groups: InteractionGroups) -> Option<()> {
+    let body = self.rigid_body_set.get(self.body_handle(key)?)?;
+    for handle in body.colliders() {
+      self.collider_set[*handle].set_solver_groups(groups);
+    }
+    Some(())
+  }
+  // Highlights one entity at a time, None clears the selection
+  pub fn select(&mut self, key: Option<&str>) -> Option<()> {
+    let selected = match key {
+      Some(key) => Some(self.ids.iter().position(|p| p == key)?),
+      None => None,
+    };
+    if let Some(previous) = self.selected {
+      self.meshes[previous].highlight = None;
+    }
+    if let Some(selected) = selected {
+      self.meshes[selected].highlight = Some(SELECTION);
+    }
+    self.selected = selected;
+    Some(())
+  }
+  pub fn selected(&self) -> Option<&str> {
+    self.selected.map(|key| self.ids[key].as_str())
   }
   pub fn get_body(&self, key: &str) -> Option<&RigidBody> {
     let key = self.ids.iter().position(|p| p == key)?;
@@ -143,3 +771,98 @@ impl Scene {
     Some(body)
   }
 }
+
+// Colliders belong to the entity whose body they are attached to
+fn entity_name(
+  ids: &[String],
+  r_handles: &[RigidBodyHandle],
+  colliders: &ColliderSet,
+  handle: ColliderHandle,
+) -> Option<String> {
+  let parent = colliders.get(handle)?.parent()?;
+  let index = r_handles.iter().position(|h| *h == parent)?;
+  Some(ids[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Geometry, Recording};
+  use genmesh::generators::{Cube, IcoSphere};
+  use rapier3d::prelude::RigidBodyBuilder;
+
+  fn scene() -> Scene {
+    let mut scene = Scene::new();
+    scene.set_gravity(Gravity::Uniform(vector![0., -9.8, 0.]));
+    let ground = RigidBodyBuilder::fixed()
+      .translation(vector![0., -10., 0.])
+      .build();
+    let cube = Mesh::placeholder(Geometry::from_genmesh(&Cube::new()));
+    scene.add_w_scale_shape("ground", cube, ground, ColliderShape::TriMesh, 9.);
+    for i in 0..4 {
+      let body = RigidBodyBuilder::dynamic()
+        .translation(vector![i as f32 * 0.3, 2. + i as f32 * 1.5, 0.])
+        .angvel(vector![1., 0., i as f32])
+        .build();
+      let sphere = Mesh::placeholder(Geometry::from_genmesh(&IcoSphere::subdivide(1)));
+      let name = format!("ball{}", i);
+      scene.add_w_scale_shape(&name, sphere, body, ColliderShape::BoundingBall, 0.5);
+    }
+    scene
+  }
+
+  // Exact bits, so the comparison can't hide any drift
+  fn positions(scene: &Scene) -> Vec<[u32; 7]> {
+    let bodies = scene
+      .r_handles
+      .iter()
+      .map(|handle| &scene.rigid_body_set[*handle]);
+    bodies
+      .map(|body| {
+        let (t, r) = (body.translation(), body.rotation().coords);
+        [t.x, t.y, t.z, r.x, r.y, r.z, r.w].map(f32::to_bits)
+      })
+      .collect()
+  }
+
+  fn run(scene: &mut Scene, steps: usize) {
+    for _ in 0..steps {
+      scene.update(scene.timestep(), |_, _| {}).unwrap();
+    }
+  }
+
+  #[test]
+  fn snapshot_restores_exactly() {
+    let mut scene = scene();
+    run(&mut scene, 30);
+    let snapshot = scene.snapshot().unwrap();
+    run(&mut scene, 60);
+    let expected = positions(&scene);
+
+    scene.load_snapshot(&snapshot).unwrap();
+    run(&mut scene, 60);
+    assert_eq!(positions(&scene), expected);
+  }
+
+  #[test]
+  fn replay_matches_recorded_run() {
+    let push = |scene: &mut Scene, force: &f32, _: f32| {
+      let body = &mut scene.rigid_body_set[scene.r_handles[1]];
+      body.apply_impulse(vector![*force, 0., 0.], true);
+    };
+    let mut scene = scene();
+    let mut recording = Recording::start(&scene).unwrap();
+    for frame in 0..40 {
+      // Uneven frame times, so some frames take no step and some several
+      let (dt, force) = (0.004 + (frame % 4) as f32 * 0.01, frame as f32 * 0.01);
+      scene
+        .update(dt, |scene, timestep| push(scene, &force, timestep))
+        .unwrap();
+      recording.record(dt, force);
+    }
+
+    let mut replayed = self::scene();
+    recording.replay(&mut replayed, push).unwrap();
+    assert_eq!(positions(&replayed), positions(&scene));
+  }
+}
@@ -14,6 +14,8 @@ struct Uniforms {
   model_view_proj: mat4x4<f32>,
   color: vec4<f32>,
   material_type: f32,
+  // Tint color, with the amount in alpha
+  highlight: vec4<f32>,
 }
 
 @group(0) @binding(0)
@@ -37,16 +39,17 @@ fn vs_main(input: VertexInput ) -> VertexOutput {
 @fragment
 fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
   let texel = textureSample(tex_diffuse, tex_sampler, output.tex_coords);
+  var color = uniforms.color;
   if uniforms.material_type == 1. {
-      return vec4(output.vertex_colors,1.0);
+      color = vec4(output.vertex_colors,1.0);
   }
   if uniforms.material_type == 2. {
       let a = texel.a;
       let r = a * texel.r + (1.0 - a) * uniforms.color.r;
       let g = a * texel.g + (1.0 - a) * uniforms.color.g;
       let b = a * texel.b + (1.0 - a) * uniforms.color.b;
-      return vec4(r,g,b,1.);
+      color = vec4(r,g,b,1.);
   }
-  return uniforms.color;
+  return vec4(mix(color.rgb, uniforms.highlight.rgb, uniforms.highlight.a), color.a);
 }
 
@@ -0,0 +1,25 @@
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(0) color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view_proj: mat4x4<f32>;
+
+@vertex
+fn vs_main(input: VertexInput) -> VertexOutput {
+  var output: VertexOutput;
+  output.position = view_proj * vec4<f32>(input.position, 1.0);
+  output.color = input.color;
+  return output;
+}
+
+@fragment
+fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
+  return output.color;
+}
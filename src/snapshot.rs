@@ -0,0 +1,76 @@
+use crate::Scene;
+use std::collections::VecDeque;
+
+// Snapshots of the last few seconds of simulation, taken every `interval` steps
+pub struct History {
+  snapshots: VecDeque<(u64, Vec<u8>)>,
+  capacity: usize,
+  interval: u64,
+}
+
+impl History {
+  pub fn new(seconds: f32, interval: u64, timestep: f32) -> Self {
+    let interval = interval.max(1);
+    let capacity = (seconds / (timestep * interval as f32)).ceil().max(1.) as usize;
+    Self {
+      snapshots: VecDeque::with_capacity(capacity),
+      capacity,
+      interval,
+    }
+  }
+  pub fn is_due(&self, step: u64) -> bool {
+    step.is_multiple_of(self.interval)
+  }
+  pub fn push(&mut self, step: u64, snapshot: Vec<u8>) {
+    if self.snapshots.len() == self.capacity {
+      self.snapshots.pop_front();
+    }
+    self.snapshots.push_back((step, snapshot));
+  }
+  // Drops everything after the newest snapshot taken at or before `step` and returns it
+  pub fn rewind_to(&mut self, step: u64) -> Option<&[u8]> {
+    while self.snapshots.back()?.0 > step {
+      self.snapshots.pop_back();
+    }
+    self.snapshots.back().map(|(_, snapshot)| &snapshot[..])
+  }
+}
+
+// Inputs applied on every update, replayed against the snapshot they started from.
+// Replays are exact on the same build; across platforms rapier needs "enhanced-determinism".
+pub struct Recording<I> {
+  start: Vec<u8>,
+  frames: Vec<(f32, I)>,
+}
+
+impl<I> Recording<I> {
+  pub fn start(scene: &Scene) -> bincode::Result<Self> {
+    Ok(Self {
+      start: scene.snapshot()?,
+      frames: Vec::new(),
+    })
+  }
+  // `dt` is what the frame passed to `Scene::update`, `input` what its steps acted on
+  pub fn record(&mut self, dt: f32, input: I) {
+    self.frames.push((dt, input));
+  }
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+  // Runs every frame through `Scene::update` again, so the accumulator, interpolation and
+  // history end up as they did live. `apply` is the `on_step` callback, with the frame's input
+  pub fn replay(
+    &self,
+    scene: &mut Scene,
+    mut apply: impl FnMut(&mut Scene, &I, f32),
+  ) -> bincode::Result<()> {
+    scene.load_snapshot(&self.start)?;
+    for (dt, input) in self.frames.iter() {
+      scene.update(*dt, |scene, timestep| apply(scene, input, timestep))?;
+    }
+    Ok(())
+  }
+}
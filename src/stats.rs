@@ -0,0 +1,241 @@
+use fluid::{Context, Signal};
+use fluid_macro::html;
+use gloo_utils::window;
+use js_sys::BigUint64Array;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+  gpu_buffer_usage, gpu_map_mode, GpuBuffer, GpuBufferDescriptor, GpuCommandEncoder, GpuDevice,
+  GpuQuerySet, GpuQuerySetDescriptor, GpuQueryType, GpuRenderPassTimestampWrites, Node,
+};
+
+const HISTORY: usize = 120;
+const PUBLISH_INTERVAL: f64 = 250.;
+const GRAPH_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+pub fn now() -> f64 {
+  window()
+    .performance()
+    .expect("Window has no performance")
+    .now()
+}
+
+// Runs the closure and returns how long it took in milliseconds
+pub fn time(f: impl FnOnce()) -> f64 {
+  let start = now();
+  f();
+  now() - start
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RenderStats {
+  pub draw_calls: u32,
+  pub triangles: u32,
+  pub uploads: u32,
+  pub upload_bytes: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct FrameSample {
+  frame: f64,
+  physics: f64,
+  render: f64,
+  gpu: Option<f64>,
+  counts: RenderStats,
+}
+
+pub struct Stats {
+  samples: VecDeque<FrameSample>,
+  current: FrameSample,
+  last_frame: Option<f64>,
+  last_publish: f64,
+  shown: Rc<Signal<bool>>,
+  summary: Rc<Signal<String>>,
+  graph: Rc<Signal<String>>,
+}
+
+impl Stats {
+  pub fn new(context: &Context) -> Self {
+    Self {
+      samples: VecDeque::with_capacity(HISTORY),
+      current: FrameSample::default(),
+      last_frame: None,
+      last_publish: 0.,
+      shown: context.create_signal(false),
+      summary: context.create_signal(String::new()),
+      graph: context.create_signal(String::new()),
+    }
+  }
+  pub fn mount(&self, ctx: &Context, parent: &Node) -> Result<(), JsValue> {
+    let shown = self.shown.clone();
+    let summary = self.summary.clone();
+    let graph = self.graph.clone();
+    let ui = html! {
+        div class=[ctx, [shown] -> &format!("stats {}", if *shown.get() {"shown"} else {""})] {
+            pre {[ctx, [summary] -> &format!("{}", summary.get())]}
+            pre class="graph" {[ctx, [graph] -> &format!("{}", graph.get())]}
+        }
+    };
+    parent.append_child(&ui)?;
+    Ok(())
+  }
+  pub fn shown(&self) -> bool {
+    *self.shown.get()
+  }
+  pub fn toggle(&self) {
+    self.shown.set(!self.shown());
+  }
+  pub fn record_physics(&mut self, ms: f64) {
+    self.current.physics += ms;
+  }
+  pub fn record_render(&mut self, ms: f64, counts: RenderStats, gpu: Option<f64>) {
+    self.current.render += ms;
+    self.current.counts = counts;
+    self.current.gpu = gpu;
+  }
+  pub fn end_frame(&mut self) {
+    let now = now();
+    let sample = std::mem::take(&mut self.current);
+    let last_frame = self.last_frame.replace(now);
+    // Gaps longer than a second are pauses, not slow frames
+    let Some(frame) = last_frame.map(|last| now - last).filter(|dt| *dt < 1000.) else {
+      return;
+    };
+    if self.samples.len() == HISTORY {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(FrameSample { frame, ..sample });
+    if self.shown() && now - self.last_publish > PUBLISH_INTERVAL {
+      self.last_publish = now;
+      self.publish();
+    }
+  }
+  fn average(&self, f: impl Fn(&FrameSample) -> f64) -> f64 {
+    self.samples.iter().map(f).sum::<f64>() / self.samples.len().max(1) as f64
+  }
+  fn publish(&self) {
+    let frame = self.average(|s| s.frame);
+    let gpu = self
+      .samples
+      .iter()
+      .filter_map(|s| s.gpu)
+      .fold((0., 0), |(sum, n), ms| (sum + ms, n + 1));
+    let gpu = if gpu.1 > 0 {
+      format!("{:6.2} ms", gpu.0 / gpu.1 as f64)
+    } else {
+      "   n/a".to_string()
+    };
+    let counts = self.samples.back().map(|s| s.counts).unwrap_or_default();
+    self.summary.set(format!(
+      "frame   {:6.2} ms ({:.0} fps)\nphysics {:6.2} ms\nrender  {:6.2} ms\ngpu     {}\ndraws {}  tris {}  uploads {} ({:.1} KB)",
+      frame,
+      1000. / frame.max(f64::EPSILON),
+      self.average(|s| s.physics),
+      self.average(|s| s.render),
+      gpu,
+      counts.draw_calls,
+      counts.triangles,
+      counts.uploads,
+      counts.upload_bytes as f64 / 1024.,
+    ));
+    let max = self
+      .samples
+      .iter()
+      .map(|s| s.frame)
+      .fold(1000. / 30., f64::max);
+    self.graph.set(
+      self
+        .samples
+        .iter()
+        .map(|s| {
+          let level = (s.frame / max * (GRAPH_LEVELS.len() - 1) as f64).round() as usize;
+          GRAPH_LEVELS[level.min(GRAPH_LEVELS.len() - 1)]
+        })
+        .collect(),
+    );
+  }
+}
+
+// Measures the frame's render passes with timestamp queries when the device supports them
+pub struct GpuTimer {
+  query_set: GpuQuerySet,
+  resolve_buffer: GpuBuffer,
+  read_buffer: GpuBuffer,
+  mapping: Rc<Cell<bool>>,
+  elapsed: Rc<Cell<Option<f64>>>,
+}
+
+impl GpuTimer {
+  pub fn new(device: &GpuDevice) -> Option<Self> {
+    if !device.features().has("timestamp-query") {
+      return None;
+    }
+    let query_set =
+      device.create_query_set(&GpuQuerySetDescriptor::new(2, GpuQueryType::Timestamp));
+    let resolve_buffer = device.create_buffer(&GpuBufferDescriptor::new(
+      16.,
+      gpu_buffer_usage::QUERY_RESOLVE | gpu_buffer_usage::COPY_SRC,
+    ));
+    let read_buffer = device.create_buffer(&GpuBufferDescriptor::new(
+      16.,
+      gpu_buffer_usage::MAP_READ | gpu_buffer_usage::COPY_DST,
+    ));
+    Some(Self {
+      query_set,
+      resolve_buffer,
+      read_buffer,
+      mapping: Rc::new(Cell::new(false)),
+      elapsed: Rc::new(Cell::new(None)),
+    })
+  }
+  // With several passes a frame, the first one starts the measurement and the last one ends it
+  pub fn timestamp_writes(&self, first: bool, last: bool) -> GpuRenderPassTimestampWrites {
+    let mut writes = GpuRenderPassTimestampWrites::new(&self.query_set);
+    if first {
+      writes.beginning_of_pass_write_index(0);
+    }
+    if last {
+      writes.end_of_pass_write_index(1);
+    }
+    writes
+  }
+  // Returns false while the previous readback is still mapped
+  pub fn resolve(&self, command_encoder: &GpuCommandEncoder) -> bool {
+    if self.mapping.get() {
+      return false;
+    }
+    command_encoder.resolve_query_set_with_u32(&self.query_set, 0, 2, &self.resolve_buffer, 0);
+    command_encoder.copy_buffer_to_buffer_with_u32_and_u32_and_u32(
+      &self.resolve_buffer,
+      0,
+      &self.read_buffer,
+      0,
+      16,
+    );
+    true
+  }
+  pub fn read(&self) {
+    self.mapping.set(true);
+    let buffer = self.read_buffer.clone();
+    let mapping = self.mapping.clone();
+    let elapsed = self.elapsed.clone();
+    spawn_local(async move {
+      if JsFuture::from(buffer.map_async(gpu_map_mode::READ))
+        .await
+        .is_ok()
+      {
+        let times = BigUint64Array::new(&buffer.get_mapped_range()).to_vec();
+        buffer.unmap();
+        elapsed.set(Some(times[1].saturating_sub(times[0]) as f64 / 1e6));
+      }
+      mapping.set(false);
+    });
+  }
+  // Each readback is handed out once, frames in between have no sample of their own
+  pub fn elapsed(&self) -> Option<f64> {
+    self.elapsed.take()
+  }
+}
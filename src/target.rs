@@ -0,0 +1,137 @@
+use crate::iter_to_array;
+use crate::renderer::{Color, DepthMode, Renderer};
+use gloo_utils::format::JsValueSerdeExt;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+use web_sys::{
+  gpu_texture_usage, GpuDevice, GpuLoadOp, GpuRenderPassColorAttachment,
+  GpuRenderPassDepthStencilAttachment, GpuStoreOp, GpuTexture, GpuTextureDescriptor,
+  GpuTextureFormat,
+};
+
+struct Textures {
+  color: GpuTexture,
+  depth: GpuTexture,
+  depth_mode: DepthMode,
+}
+
+// An offscreen texture a camera renders into and materials sample from, e.g. for monitors,
+// mirrors and portals. Clones share the same textures, so meshes built from one see every frame.
+// Meshes bind the color texture when they are built and never look it up again; only `restore`
+// replaces it, and the meshes sampling the target have to be restored after it.
+// Nothing stops two targets from sampling each other, the one rendered first then shows what
+// the other had in the previous frame
+#[derive(Clone)]
+pub struct RenderTarget {
+  textures: Rc<RefCell<Textures>>,
+  width: u32,
+  height: u32,
+  // Whether depth is kept after the pass and can be sampled
+  keep_depth: bool,
+}
+
+impl RenderTarget {
+  pub fn new(renderer: &Renderer, width: u32, height: u32, keep_depth: bool) -> Self {
+    let (width, height) = (width.max(1), height.max(1));
+    let device = renderer.device();
+    // Switched on the first pass if the camera uses another mode
+    let depth_mode = DepthMode::default();
+    let textures = Textures {
+      color: Self::create_color(device, renderer.format(), width, height),
+      depth: Self::create_depth(device, width, height, keep_depth, depth_mode),
+      depth_mode,
+    };
+    Self {
+      textures: Rc::new(RefCell::new(textures)),
+      width,
+      height,
+      keep_depth,
+    }
+  }
+  fn create_color(
+    device: &GpuDevice,
+    format: GpuTextureFormat,
+    width: u32,
+    height: u32,
+  ) -> GpuTexture {
+    device.create_texture(&GpuTextureDescriptor::new(
+      format,
+      &iter_to_array([width, height]),
+      gpu_texture_usage::RENDER_ATTACHMENT
+        | gpu_texture_usage::TEXTURE_BINDING
+        | gpu_texture_usage::COPY_SRC,
+    ))
+  }
+  fn create_depth(
+    device: &GpuDevice,
+    width: u32,
+    height: u32,
+    keep_depth: bool,
+    depth_mode: DepthMode,
+  ) -> GpuTexture {
+    let mut usage = gpu_texture_usage::RENDER_ATTACHMENT;
+    if keep_depth {
+      usage |= gpu_texture_usage::TEXTURE_BINDING;
+    }
+    device.create_texture(&GpuTextureDescriptor::new(
+      depth_mode.format(),
+      &iter_to_array([width, height]),
+      usage,
+    ))
+  }
+  // Textures from a lost device are useless, this recreates them on the renderer's new one;
+  // meshes sampling the target keep the old color texture until they are restored
+  pub fn restore(&self, renderer: &Renderer) {
+    let device = renderer.device();
+    let mut textures = self.textures.borrow_mut();
+    textures.color = Self::create_color(device, renderer.format(), self.width, self.height);
+    textures.depth = Self::create_depth(
+      device,
+      self.width,
+      self.height,
+      self.keep_depth,
+      textures.depth_mode,
+    );
+  }
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+  pub fn color(&self) -> GpuTexture {
+    self.textures.borrow().color.clone()
+  }
+  pub fn depth(&self) -> Option<GpuTexture> {
+    self
+      .keep_depth
+      .then(|| self.textures.borrow().depth.clone())
+  }
+  pub fn same(&self, other: &RenderTarget) -> bool {
+    Rc::ptr_eq(&self.textures, &other.textures)
+  }
+  // Attachments for a pass with a camera in `depth_mode`
+  pub(crate) fn attachments(
+    &self,
+    device: &GpuDevice,
+    depth_mode: DepthMode,
+  ) -> (
+    GpuRenderPassColorAttachment,
+    GpuRenderPassDepthStencilAttachment,
+  ) {
+    let mut textures = self.textures.borrow_mut();
+    if textures.depth_mode != depth_mode {
+      textures.depth =
+        Self::create_depth(device, self.width, self.height, self.keep_depth, depth_mode);
+      textures.depth_mode = depth_mode;
+    }
+    let mut color = GpuRenderPassColorAttachment::new(
+      GpuLoadOp::Clear,
+      GpuStoreOp::Store,
+      &textures.color.create_view(),
+    );
+    color.clear_value(&JsValue::from_serde(&Color::rgb(0.1, 0.1, 0.1)).unwrap());
+    (color, depth_mode.attachment(&textures.depth))
+  }
+}
@@ -1,11 +1,143 @@
-use nalgebra::{Isometry3, Matrix4, Perspective3, Unit, UnitQuaternion, Vector3};
+use crate::camera::{CameraController, CameraInput, Follow, Follower, Orbit};
+use crate::path::{CameraPath, Easing};
+use crate::renderer::DepthMode;
+use nalgebra::{
+  Isometry3, Matrix4, Orthographic3, Perspective3, Point3, Vector2, Vector3, Vector4,
+};
 use std::f32::consts::PI;
 use web_sys::HtmlCanvasElement;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+  // Vertical field of view in radians
+  Perspective { fov: f32, near: f32, far: f32 },
+  // Reverse-Z perspective with no far plane, near maps to depth 1 and infinity to 0
+  InfinitePerspective { fov: f32, near: f32 },
+  // Height of the view volume in world units, the width follows the aspect ratio
+  Orthographic { height: f32, near: f32, far: f32 },
+}
+
+// nalgebra's projections map depth to OpenGL's -1..=1, WebGPU clips everything outside 0..=1
+#[rustfmt::skip]
+const OPENGL_TO_WGPU: Matrix4<f32> = Matrix4::new(
+  1., 0., 0., 0.,
+  0., 1., 0., 0.,
+  0., 0., 0.5, 0.5,
+  0., 0., 0., 1.,
+);
+
+impl Default for Projection {
+  fn default() -> Self {
+    Self::InfinitePerspective {
+      fov: PI * 0.4,
+      near: 0.1,
+    }
+  }
+}
+
+impl Projection {
+  pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+    match *self {
+      Self::Perspective { fov, near, far } => {
+        OPENGL_TO_WGPU * Perspective3::new(aspect, fov, near, far).to_homogeneous()
+      }
+      Self::InfinitePerspective { fov, near } => {
+        let f = 1. / (fov / 2.).tan();
+        #[rustfmt::skip]
+        let matrix = Matrix4::new(
+          f / aspect, 0., 0., 0.,
+          0., f, 0., 0.,
+          0., 0., 0., near,
+          0., 0., -1., 0.,
+        );
+        matrix
+      }
+      Self::Orthographic { height, near, far } => {
+        let (x, y) = (height * aspect / 2., height / 2.);
+        OPENGL_TO_WGPU * Orthographic3::new(-x, x, -y, y, near, far).to_homogeneous()
+      }
+    }
+  }
+  pub fn fov(&self) -> Option<f32> {
+    match *self {
+      Self::Perspective { fov, .. } | Self::InfinitePerspective { fov, .. } => Some(fov),
+      Self::Orthographic { .. } => None,
+    }
+  }
+  pub fn with_fov(self, fov: f32) -> Self {
+    match self {
+      Self::Perspective { near, far, .. } => Self::Perspective { fov, near, far },
+      Self::InfinitePerspective { near, .. } => Self::InfinitePerspective { fov, near },
+      orthographic => orthographic,
+    }
+  }
+  pub fn depth_mode(&self) -> DepthMode {
+    match self {
+      Self::InfinitePerspective { .. } => DepthMode::ReverseZ,
+      _ => DepthMode::Standard,
+    }
+  }
+}
+
+// Part of the canvas a viewport draws to, as fractions of its size from the top left
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+}
+
+impl Default for Region {
+  fn default() -> Self {
+    Self::new(0., 0., 1., 1.)
+  }
+}
+
+impl Region {
+  pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+    Self {
+      x,
+      y,
+      width,
+      height,
+    }
+  }
+  // x, y, width and height in pixels, kept inside the canvas
+  pub fn pixels(&self, canvas_width: u32, canvas_height: u32) -> (u32, u32, u32, u32) {
+    let (w, h) = (canvas_width as f32, canvas_height as f32);
+    let x = ((self.x * w).round() as u32).min(canvas_width);
+    let y = ((self.y * h).round() as u32).min(canvas_height);
+    let width = ((self.width * w).round() as u32).min(canvas_width - x);
+    let height = ((self.height * h).round() as u32).min(canvas_height - y);
+    (x, y, width, height)
+  }
+}
+
+fn canvas_size(canvas: &HtmlCanvasElement) -> Vector2<f32> {
+  Vector2::new(canvas.width() as f32, canvas.height() as f32)
+}
+
+struct Playback {
+  path: CameraPath,
+  time: f32,
+  // Seconds spent easing back into the controller after the path ends
+  blend: f32,
+}
+
 pub struct Viewport {
   view: Isometry3<f32>,
   target: Isometry3<f32>,
-  proj: Perspective3<f32>,
+  follower: Follower,
+  focus: Point3<f32>,
+  projection: Projection,
+  canvas: Vector2<f32>,
+  region: Region,
+  size: Vector2<f32>,
+  proj: Matrix4<f32>,
+  controller: Box<dyn CameraController>,
+  input: CameraInput,
+  playback: Option<Playback>,
   zoom: bool,
   rotate: bool,
 }
@@ -17,18 +149,26 @@ pub struct Viewport {
 impl Viewport {
   pub fn new(canvas: &HtmlCanvasElement) -> Self {
     let target = Isometry3::identity();
-    let proj = Perspective3::new(
-      canvas.width() as f32 / canvas.height() as f32,
-      PI * 0.4,
-      0.1,
-      100000.,
-    );
-    let eye = [0., 0., 10.].into();
-    let view = Isometry3::look_at_rh(&eye, &target.translation.vector.into(), &Vector3::y());
+    let projection = Projection::default();
+    let canvas = canvas_size(canvas);
+    let region = Region::default();
+    let size = canvas.map(|v| v.max(1.));
+    let mut controller = Orbit::new(10.);
+    let input = CameraInput::default();
+    let view = controller.update(&target, &input, 0.);
     Self {
       view,
       target,
-      proj,
+      follower: Follower::new(Follow::default()),
+      focus: Point3::origin(),
+      projection,
+      canvas,
+      region,
+      size,
+      proj: projection.matrix(size.x / size.y),
+      controller: Box::new(controller),
+      input,
+      playback: None,
       zoom: false,
       rotate: false,
     }
@@ -36,40 +176,228 @@ impl Viewport {
   pub fn follow(&mut self, target: Isometry3<f32>) {
     self.target = target;
   }
+  pub fn follow_options(&self) -> Follow {
+    self.follower.follow
+  }
+  pub fn set_follow_options(&mut self, follow: Follow) {
+    self.follower.follow = follow;
+  }
+  pub fn set_controller(&mut self, controller: impl CameraController + 'static) {
+    self.controller = Box::new(controller);
+  }
+  pub fn captures_movement(&self) -> bool {
+    self.controller.captures_movement()
+  }
+  // Takes over the camera until the path ends, then blends back into the controller
+  pub fn play(&mut self, path: CameraPath, blend: f32) {
+    self.playback = Some(Playback {
+      path,
+      time: 0.,
+      blend,
+    });
+  }
+  pub fn stop(&mut self) {
+    self.playback = None;
+    self.proj = self.projection.matrix(self.size.x / self.size.y);
+  }
+  pub fn is_playing(&self) -> bool {
+    self.playback.is_some()
+  }
+  // Runs the controller with the input gathered since the last frame
+  pub fn update(&mut self, dt: f32) {
+    let target = self.follower.update(&self.target, dt);
+    self.focus = target.translation.vector.into();
+    // The controller keeps tracking the target during a path, but without the user's input
+    let input = if self.is_playing() {
+      CameraInput::default()
+    } else {
+      self.input
+    };
+    self.view = self.controller.update(&target, &input, dt);
+    self.input.look = Default::default();
+    self.input.zoom = 0.;
+    self.update_playback(dt);
+  }
+  fn update_playback(&mut self, dt: f32) {
+    let Some(playback) = &mut self.playback else {
+      return;
+    };
+    playback.time += dt;
+    let duration = playback.path.duration();
+    let Some((view, fov)) = playback.path.sample(playback.time.min(duration)) else {
+      return self.stop();
+    };
+    let blend = match playback.time - duration {
+      over if over <= 0. => 0.,
+      over if playback.blend > 0. => Easing::EaseInOut.apply(over / playback.blend),
+      _ => 1.,
+    };
+    if blend >= 1. {
+      return self.stop();
+    }
+    // Blends the camera transforms, so the eye moves in a straight line
+    let (from, to) = (view.inverse(), self.view.inverse());
+    let camera = from.try_lerp_slerp(&to, blend, f32::EPSILON).unwrap_or(to);
+    self.view = camera.inverse();
+    let fov = fov + (self.projection.fov().unwrap_or(fov) - fov) * blend;
+    self.proj = self
+      .projection
+      .with_fov(fov)
+      .matrix(self.size.x / self.size.y);
+  }
+  // Pulls the camera in front of whatever `cast` hits between the target and the eye;
+  // `cast` takes an origin, a unit direction and a distance and returns the hit distance
+  pub fn avoid_obstacles(&mut self, cast: impl Fn(Point3<f32>, Vector3<f32>, f32) -> Option<f32>) {
+    if self.captures_movement() || self.is_playing() {
+      return;
+    }
+    let mut camera = self.view.inverse();
+    let offset = Point3::from(camera.translation.vector) - self.focus;
+    let distance = offset.norm();
+    let Some(dir) = offset.try_normalize(f32::EPSILON) else {
+      return;
+    };
+    let margin = self.follower.follow.margin;
+    if let Some(toi) = cast(self.focus, dir, distance + margin) {
+      let eye = self.focus + dir * (toi - margin).clamp(0., distance);
+      camera.translation.vector = eye.coords;
+      self.view = camera.inverse();
+    }
+  }
+  pub fn view(&self) -> Isometry3<f32> {
+    self.view
+  }
   pub fn view_cube(&self) -> Matrix4<f32> {
-    self.proj.to_homogeneous() * self.view.rotation.to_homogeneous()
+    self.proj * self.view.rotation.to_homogeneous()
   }
   pub fn view_proj(&self) -> Matrix4<f32> {
-    self.proj.to_homogeneous() * self.view.to_homogeneous() * self.target.inverse().to_homogeneous()
+    self.proj * self.view.to_homogeneous()
+  }
+  pub fn eye(&self) -> Point3<f64> {
+    self.view.cast::<f64>().inverse().translation.vector.into()
+  }
+  // Moves the model next to the camera in f64 first, so large world coordinates
+  // cancel out before anything is rounded to f32
+  pub fn model_view_proj(&self, model: &Matrix4<f32>) -> Matrix4<f32> {
+    let mut relative = model.cast::<f64>();
+    let translation = relative.fixed_view::<3, 1>(0, 3) - self.eye().coords;
+    relative
+      .fixed_view_mut::<3, 1>(0, 3)
+      .copy_from(&translation);
+    self.relative_view_proj() * relative.cast::<f32>()
   }
+  // For positions that are already relative to the eye
+  pub fn relative_view_proj(&self) -> Matrix4<f32> {
+    self.proj * self.view.rotation.to_homogeneous()
+  }
+  pub fn projection(&self) -> Projection {
+    self.projection
+  }
+  pub fn set_projection(&mut self, projection: Projection) {
+    self.projection = projection;
+    self.proj = projection.matrix(self.size.x / self.size.y);
+  }
+  // Only the aspect ratio changes, the projection stays as configured
   pub fn resize(&mut self, canvas: &HtmlCanvasElement) {
-    self.proj = Perspective3::new(
-      canvas.width() as f32 / canvas.height() as f32,
-      PI / 2.,
-      0.2,
-      10000.,
-    );
+    self.resize_to(canvas.width(), canvas.height());
+  }
+  // For viewports drawing into something other than the canvas, e.g. a render target
+  pub fn resize_to(&mut self, width: u32, height: u32) {
+    self.canvas = Vector2::new(width as f32, height as f32);
+    self.update_size();
+  }
+  pub fn region(&self) -> Region {
+    self.region
+  }
+  pub fn set_region(&mut self, region: Region) {
+    self.region = region;
+    self.update_size();
+  }
+  fn update_size(&mut self) {
+    let (_, _, width, height) = self
+      .region
+      .pixels(self.canvas.x as u32, self.canvas.y as u32);
+    self.size = Vector2::new(width.max(1) as f32, height.max(1) as f32);
+    self.proj = self.projection.matrix(self.size.x / self.size.y);
+  }
+  fn origin(&self) -> Vector2<f32> {
+    let (x, y, _, _) = self
+      .region
+      .pixels(self.canvas.x as u32, self.canvas.y as u32);
+    Vector2::new(x as f32, y as f32)
+  }
+  // Canvas pixel at the middle of the region
+  pub fn center(&self) -> Vector2<f32> {
+    self.origin() + self.size / 2.
+  }
+  // Whether a canvas pixel falls inside this viewport
+  pub fn contains(&self, x: f32, y: f32) -> bool {
+    let local = Vector2::new(x, y) - self.origin();
+    (0. ..self.size.x).contains(&local.x) && (0. ..self.size.y).contains(&local.y)
+  }
+  // Canvas pixels, with y pointing down, to normalized device coordinates
+  fn to_ndc(&self, x: f32, y: f32) -> Vector2<f32> {
+    let local = Vector2::new(x, y) - self.origin();
+    Vector2::new(
+      2. * local.x / self.size.x - 1.,
+      1. - 2. * local.y / self.size.y,
+    )
+  }
+  // Origin and unit direction of the ray through a canvas pixel
+  pub fn screen_to_ray(&self, x: f32, y: f32) -> Option<(Point3<f32>, Vector3<f32>)> {
+    let ndc = self.to_ndc(x, y);
+    let inverse = self.proj.try_inverse()?;
+    let camera = self.view.inverse();
+    // Two depths that are finite in either depth mode
+    let (near, far) = match self.projection.depth_mode() {
+      DepthMode::ReverseZ => (1., 0.5),
+      DepthMode::Standard => (0., 1.),
+    };
+    let unproject = |z: f32| {
+      let point = inverse * Vector4::new(ndc.x, ndc.y, z, 1.);
+      camera * Point3::from(point.xyz() / point.w)
+    };
+    let origin = unproject(near);
+    let dir = (unproject(far) - origin).try_normalize(f32::EPSILON)?;
+    Some((origin, dir))
+  }
+  // Canvas pixel of a world point, None when it's behind the camera; it may be outside the region
+  pub fn world_to_screen(&self, point: &Point3<f32>) -> Option<Vector2<f32>> {
+    let clip = self.model_view_proj(&Matrix4::new_translation(&point.coords)) * Vector4::w();
+    if clip.w <= 0. {
+      return None;
+    }
+    let ndc = clip.xy() / clip.w;
+    Some(
+      self.origin()
+        + Vector2::new(
+          (ndc.x + 1.) / 2. * self.size.x,
+          (1. - ndc.y) / 2. * self.size.y,
+        ),
+    )
+  }
+  pub fn size(&self) -> Vector2<f32> {
+    self.size
   }
   pub fn update_zoom(&mut self, ds: i32) {
     if self.zoom && ds != 0 {
-      let delta = if ds > 0 { 1.05 } else { 0.95 };
-      self.view.translation.vector = delta * self.view.translation.vector;
+      self.input.zoom += ds.signum() as f32;
     }
   }
-  pub fn update_rot(&mut self, dx: i32, dy: i32, _dt: f32) {
+  pub fn update_rot(&mut self, dx: i32, dy: i32) {
     if self.rotate {
-      let pitch = dy as f32 * 0.002;
-      let yaw = dx as f32 * 0.002;
-      let delta_rot = {
-        let axis = Unit::new_normalize(self.view.rotation.conjugate() * Vector3::x());
-        let q_ver = UnitQuaternion::from_axis_angle(&axis, pitch);
-        let axis = Unit::new_normalize(self.target.rotation.conjugate() * Vector3::y());
-        let q_hor = UnitQuaternion::from_axis_angle(&axis, yaw);
-        q_ver * q_hor
-      };
-      self.view.rotation *= &delta_rot;
+      self.input.look.x += dx as f32;
+      self.input.look.y += dy as f32;
     }
   }
+  // Right, up and forward for controllers that move on their own
+  pub fn update_movement(&mut self, movement: Vector3<f32>) {
+    self.input.movement = if self.rotate {
+      movement
+    } else {
+      Vector3::zeros()
+    };
+  }
   pub fn unlock(&mut self) {
     self.zoom = true;
     self.rotate = true;
@@ -1,10 +1,13 @@
 use genmesh::generators::IcoSphere;
-use nalgebra::{vector, Point3};
+use nalgebra::{point, vector, Point3};
 use noise::{Fbm, NoiseFn, Perlin};
-use rapier3d::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder};
+use rapier3d::dynamics::RigidBodyBuilder;
 use wasm_bindgen::JsValue;
 
-use crate::{Geometry, Material, Mesh, Renderer};
+use crate::{ColliderShape, Geometry, Gravity, Material, Mesh, Renderer};
+
+const CENTER: Point3<f32> = point![0., -1010., 0.];
+const RADIUS: f32 = 1000.;
 
 pub struct World {}
 
@@ -46,25 +49,15 @@ impl World {
         ),
       )
       .await?;
-      let vertices = geo
-        .vertices
-        .iter()
-        .map(|[x, y, z]| Point3::new(x * 1000., y * 1000., z * 1000.))
-        .collect();
-      let indices: Vec<[u32; 3]> = geo
-        .indices
-        .chunks(3)
-        .map(|v| [v[0] as u32, v[1] as u32, v[2] as u32])
-        .collect();
-      let lithocollider = ColliderBuilder::convex_mesh(vertices, &indices)
-        .unwrap()
-        .build();
-
-      let body = RigidBodyBuilder::fixed()
-        .translation(vector![0., -1010., 0.])
-        .build();
-      scene.add_w_scale_collider("lithosphere", mesh, body, lithocollider, 1000.);
+      // A trimesh keeps the terrain noise that a convex hull would smooth over
+      let body = RigidBodyBuilder::fixed().translation(CENTER.coords).build();
+      scene
+        .add_w_scale_shape("lithosphere", mesh, body, ColliderShape::TriMesh, RADIUS)
+        .ok_or_else(|| JsValue::from_str("Couldn't build the lithosphere collider"))?;
     }
     Ok(Self {})
   }
+  pub fn gravity(&self) -> Gravity {
+    Gravity::radial(CENTER, 9.8, RADIUS)
+  }
 }